@@ -0,0 +1,183 @@
+//! Renders a single frame without opening a window and saves it as a PNG.
+//!
+//! Handy for generating preview shots of maps on machines without a display.
+//! Run it with `--help` to see the available options.
+
+use std::{env, error::Error, fmt, process, sync::Arc};
+
+use raycaster::{
+    camera::Camera,
+    config::Config,
+    frame::Frame,
+    map::Map,
+    primitive::Vec2,
+    raycaster::{Raycaster, Scratch},
+    texture::TextureRegistry,
+};
+
+const USAGE: &str = "\
+Usage: snapshot --out <path> [options]
+
+Options:
+    --config <path>     Config file to use (default: Config.toml)
+    --map <path>        Map to render instead of the one in the config
+    --pos <x>,<y>       Camera position (default: player.initial_pos)
+    --dir <x>,<y>       Camera direction (default: player.initial_dir)
+    --size <wd>x<ht>    Image size (default: screen.wd x screen.ht)
+    --out <path>        Where to save the PNG";
+
+/// Max width and height of an image. Keeps the size of its buffer within `u32`.
+const MAX_SIZE: u32 = 16384;
+
+#[derive(Debug)]
+struct UsageError(String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\n\n{}", self.0, USAGE)
+    }
+}
+
+impl Error for UsageError {}
+
+#[derive(Default)]
+struct Args {
+    config: Option<String>,
+    map: Option<String>,
+    pos: Option<Vec2>,
+    dir: Option<Vec2>,
+    size: Option<(u32, u32)>,
+    out: String,
+}
+
+fn main() {
+    env_logger::init();
+
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let args = parse_args(env::args().skip(1))?;
+
+    let config = Config::read(args.config.as_deref().unwrap_or("Config.toml"))?;
+    let map_path = args.map.as_ref().unwrap_or(&config.assets.map);
+    let textures = TextureRegistry::load(&config.assets.tex)?;
+    let map = Map::load(map_path, textures.names())?;
+
+    let (scr_wd, scr_ht) = args.size.unwrap_or((config.screen.wd, config.screen.ht));
+    let tex_users = config.tex_users();
+    let player = config.player;
+    let pos = args.pos.unwrap_or(player.initial_pos);
+
+    // Rendering would panic on unknown texture ids, e.g. those of sprites.
+    map.check(textures.len(), pos, &tex_users)?;
+
+    let mut camera = Camera::new(
+        pos,
+        args.dir.unwrap_or(player.initial_dir).normalized(),
+        player.fov.unwrap_or(90.0),
+    );
+    camera.eye_ht = player.eye_ht.unwrap_or(0.5);
+    camera.set_aspect(scr_wd as f32 / scr_ht as f32);
+    let caster = Raycaster {
+        scr_wd,
+        scr_ht,
+        textures: Arc::new(textures),
+        floor_id: config.misc.floor_tex,
+        wall_ht_scale: config.misc.wall_ht_scale.unwrap_or(1.0),
+        max_dist: config.misc.max_dist.unwrap_or(100.0),
+        threads: config.misc.threads(),
+        fog: config.fog,
+        sky_id: config.misc.sky_tex,
+        mipmaps: config.misc.mipmaps.unwrap_or(true),
+        sampling: config.misc.sampling,
+        scratch: Scratch::default(),
+    };
+
+    let mut frame = Frame::new(scr_wd, scr_ht);
+    caster.render(&camera, &map, &config.sprites, &mut frame.buf);
+    frame.save(&args.out)?;
+
+    Ok(())
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut raw: I) -> Result<Args, UsageError> {
+    let mut args = Args::default();
+    let mut out = None;
+
+    while let Some(flag) = raw.next() {
+        if flag == "--help" || flag == "-h" {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+
+        let value = raw
+            .next()
+            .ok_or_else(|| UsageError(format!("Missing value for {}", flag)))?;
+
+        match flag.as_str() {
+            "--config" => args.config = Some(value),
+            "--map" => args.map = Some(value),
+            "--pos" => args.pos = Some(parse_pair(&value, ',', &flag)?),
+            "--dir" => {
+                let dir: Vec2 = parse_pair(&value, ',', &flag)?;
+
+                // Otherwise it can't be normalized.
+                let len_squared = dir.len_squared();
+                if len_squared == 0.0 || !len_squared.is_finite() {
+                    return Err(UsageError(
+                        "--dir must have a finite, non-zero length".to_string(),
+                    ));
+                }
+                args.dir = Some(dir);
+            }
+            "--size" => {
+                let (wd, ht) = parse_pair(&value, 'x', &flag)?;
+
+                if !(1..=MAX_SIZE).contains(&wd) || !(1..=MAX_SIZE).contains(&ht) {
+                    return Err(UsageError(format!(
+                        "--size must be between 1x1 and {}x{}",
+                        MAX_SIZE, MAX_SIZE
+                    )));
+                }
+                args.size = Some((wd, ht));
+            }
+            "--out" => out = Some(value),
+            _ => return Err(UsageError(format!("Unknown option {}", flag))),
+        }
+    }
+
+    args.out = out.ok_or_else(|| UsageError("--out is required".to_string()))?;
+
+    Ok(args)
+}
+
+/// Parses values like `1.5,2.5` into a `Vec2` or `640x480` into a `(u32, u32)`.
+fn parse_pair<T: FromPair>(value: &str, sep: char, flag: &str) -> Result<T, UsageError> {
+    let bad_value = || UsageError(format!("Invalid value {:?} for {}", value, flag));
+
+    let mut parts = value.splitn(2, sep);
+    let a = parts.next().ok_or_else(bad_value)?;
+    let b = parts.next().ok_or_else(bad_value)?;
+
+    T::from_pair(a.trim(), b.trim()).ok_or_else(bad_value)
+}
+
+trait FromPair: Sized {
+    fn from_pair(a: &str, b: &str) -> Option<Self>;
+}
+
+impl FromPair for Vec2 {
+    fn from_pair(a: &str, b: &str) -> Option<Self> {
+        Some(Vec2::new(a.parse().ok()?, b.parse().ok()?))
+    }
+}
+
+impl FromPair for (u32, u32) {
+    fn from_pair(a: &str, b: &str) -> Option<Self> {
+        Some((a.parse().ok()?, b.parse().ok()?))
+    }
+}
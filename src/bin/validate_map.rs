@@ -0,0 +1,83 @@
+//! Checks maps for problems like holes in the border, a spawn position inside a
+//! wall or unknown texture ids. Every problem is printed along with its cell.
+//! Texture ids given by the config (like those of sprites) are checked too.
+//!
+//! Usage: `validate_map [--config <path>] [<map>...]`
+//!
+//! If no maps are given, the map in the config is checked. Textures and the
+//! spawn position are always taken from the config. Exits with a non-zero code
+//! if any map has errors or can't be read.
+
+use std::{env, process};
+
+use raycaster::{
+    config::Config,
+    map::{Map, Severity},
+};
+
+fn main() {
+    env_logger::init();
+
+    let mut args: Vec<_> = env::args().skip(1).collect();
+    let mut config_path = "Config.toml".to_string();
+
+    if let Some(i) = args.iter().position(|arg| arg == "--config") {
+        if i + 1 >= args.len() {
+            eprintln!("Missing value for --config");
+            process::exit(2);
+        }
+        config_path = args.remove(i + 1);
+        args.remove(i);
+    }
+
+    let config = match Config::read(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", config_path, e);
+            process::exit(2);
+        }
+    };
+
+    if args.is_empty() {
+        args.push(config.assets.map.clone());
+    }
+
+    let tex_names = config.assets.tex_names();
+    let mut failed = false;
+
+    for path in &args {
+        let map = match Map::load(path, &tex_names) {
+            Ok(map) => map,
+            Err(e) => {
+                println!("{}: {}", path, e);
+                failed = true;
+                continue;
+            }
+        };
+
+        let issues = map.validate(
+            config.assets.tex.len(),
+            config.player.initial_pos,
+            &config.tex_users(),
+        );
+
+        if issues.is_empty() {
+            println!("{}: OK", path);
+        }
+
+        for issue in issues {
+            let severity = match issue.severity() {
+                Severity::Warning => "warning",
+                Severity::Error => {
+                    failed = true;
+                    "error"
+                }
+            };
+            println!("{}: {}: {}", path, severity, issue);
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
@@ -1,23 +1,57 @@
 use crate::primitive::{Ray2, Vec2};
 
+/// Aspect ratio at which `Camera::fov` is the horizontal field of view.
+pub const REF_ASPECT: f32 = 4.0 / 3.0;
+
 /// Represents a camera.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Camera {
     pub pos: Vec2,
     pub dir: Vec2,
+    /// Horizontal field of view (in degrees) on a screen with an aspect ratio
+    /// of `REF_ASPECT`. The vertical field of view stays the same on other
+    /// screens. So wider screens see more on the sides. See `set_aspect`.
     pub fov: f32,
+    /// How far the horizon is shifted down from the center of the screen as a
+    /// fraction of the screen's height. +ve values mean that the camera looks
+    /// up and -ve values mean that it looks down.
+    pub pitch: f32,
+    /// The camera's height above the floor. Walls are 1 unit tall (ignoring
+    /// scaling). So 0.5 means that the camera is at half the height of walls.
+    pub eye_ht: f32,
+    /// Width of the screen divided by its height, as seen by the player.
+    aspect: f32,
     plane: Vec2,
 }
 
 impl Camera {
-    /// `dir` should already be normalized.
+    /// `dir` should already be normalized. The camera looks straight ahead from
+    /// half the height of walls at a screen with an aspect ratio of
+    /// `REF_ASPECT`.
     pub fn new(pos: Vec2, dir: Vec2, fov: f32) -> Self {
-        Camera {
+        let mut camera = Camera {
             pos,
             fov,
             dir,
-            plane: Vec2::new(-dir.y, dir.x) * (fov / 2.0).to_radians().tan(),
-        }
+            pitch: 0.0,
+            eye_ht: 0.5,
+            aspect: REF_ASPECT,
+            plane: Vec2::new(0.0, 0.0),
+        };
+        camera.update_plane();
+        camera
+    }
+
+    /// Sets the aspect ratio of the screen the camera's view is shown on. It
+    /// widens (or narrows) the horizontal field of view. The vertical field of
+    /// view doesn't change.
+    pub fn set_aspect(&mut self, aspect: f32) {
+        self.aspect = aspect;
+        self.update_plane();
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.aspect
     }
 
     /// Returns a ray originating from camera's position and passing through
@@ -29,8 +63,55 @@ impl Camera {
         Ray2::new(self.pos, (self.dir + pct_x * self.plane).normalized())
     }
 
+    /// Transforms `pos` from world space to camera space.
+    ///
+    /// The x-coordinate of the result is in the same units as `pct_x` of
+    /// `ray`. The y-coordinate is the perpendicular distance of `pos` from the
+    /// camera plane. It is -ve if `pos` is behind the camera.
+    pub fn to_cam_space(&self, pos: Vec2) -> Vec2 {
+        let rel = pos - self.pos;
+        let depth = rel.dot(&self.dir);
+
+        Vec2::new(
+            rel.dot(&self.plane) / (self.plane.len_squared() * depth),
+            depth,
+        )
+    }
+
+    /// Returns the length of the camera plane on either side of `dir`.
+    pub fn plane_len(&self) -> f32 {
+        self.plane.len()
+    }
+
+    /// Returns half the height of the camera plane (which is at unit distance)
+    /// i.e. the tangent of half the vertical field of view.
+    pub fn plane_ht(&self) -> f32 {
+        self.plane_len() / self.aspect
+    }
+
+    /// Returns a camera part way between `self` (when `t` is 0) and `next`
+    /// (when `t` is 1). `dir` is rotated along the shorter arc.
+    pub fn lerp(&self, next: &Camera, t: f32) -> Camera {
+        let cross = self.dir.x * next.dir.y - self.dir.y * next.dir.x;
+        let angle = cross.atan2(self.dir.dot(&next.dir));
+
+        let mut res = self.clone();
+        res.pos = self.pos + t * (next.pos - self.pos);
+        res.pitch = self.pitch + t * (next.pitch - self.pitch);
+        res.eye_ht = self.eye_ht + t * (next.eye_ht - self.eye_ht);
+        res.rotate_by(t * angle);
+        res
+    }
+
     pub fn rotate_by(&mut self, rad: f32) {
         self.dir = self.dir.rotated(rad);
-        self.plane = Vec2::new(-self.dir.y, self.dir.x) * (self.fov / 2.0).to_radians().tan();
+        self.update_plane();
+    }
+
+    /// Makes the camera plane perpendicular to `dir` and as long as `fov` and
+    /// `aspect` need.
+    fn update_plane(&mut self) {
+        let half_ht = (self.fov / 2.0).to_radians().tan() / REF_ASPECT;
+        self.plane = Vec2::new(-self.dir.y, self.dir.x) * half_ht * self.aspect;
     }
 }
@@ -1,12 +1,20 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, prelude::*};
 use std::path::Path;
+use std::thread;
 
 use serde::Deserialize;
 use toml::de;
 use thiserror::Error;
 
+use crate::fog::Fog;
+use crate::frame::Scaling;
+use crate::input::{Action, Binding};
+use crate::map::TexUser;
 use crate::primitive::Vec2;
+use crate::sprite::Sprite;
+use crate::texture::{tex_name, Sampling};
 
 #[derive(Deserialize)]
 pub struct Config {
@@ -14,12 +22,30 @@ pub struct Config {
     pub assets: AssetsConfig,
     pub player: PlayerConfig,
     pub misc: MiscConfig,
+    #[serde(default)]
+    pub mouse: MouseConfig,
+    /// Bindings which replace the defaults. See `Bindings::new`.
+    #[serde(default)]
+    pub controls: HashMap<Action, Vec<Binding>>,
+    #[serde(default)]
+    pub sprites: Vec<Sprite>,
+    pub fog: Option<Fog>,
 }
 
 #[derive(Deserialize)]
 pub struct ScreenConfig {
     pub wd: u32,
     pub ht: u32,
+    /// Max frames drawn per second. Frames follow the display's refresh
+    /// rate if not set.
+    pub fps_cap: Option<f32>,
+    /// Resolution frames are rendered at before being scaled to the window.
+    /// See `render_size`.
+    pub render_wd: Option<u32>,
+    pub render_ht: Option<u32>,
+    /// How frames are scaled to the window.
+    #[serde(default)]
+    pub scaling: Scaling,
 }
 
 #[derive(Deserialize)]
@@ -31,15 +57,55 @@ pub struct AssetsConfig {
 #[derive(Deserialize)]
 pub struct PlayerConfig {
     pub fov: Option<f32>,
+    /// Movement speed in units per second.
     pub speed: f32,
+    /// Turning speed in radians per second.
+    pub turn_speed: Option<f32>,
     pub initial_dir: Vec2,
     pub initial_pos: Vec2,
+    /// Height of the player's eyes above the floor as a fraction of the
+    /// height of walls. See `Camera::eye_ht`.
+    pub eye_ht: Option<f32>,
+    /// How close the player can get to walls. Must be less than 0.5. See
+    /// `radius`.
+    pub radius: Option<f32>,
 }
 
 #[derive(Deserialize)]
 pub struct MiscConfig {
     pub floor_tex: usize,
     pub wall_ht_scale: Option<f32>,
+    pub max_dist: Option<f32>,
+    /// Number of threads to render with. Defaults to the number of CPUs.
+    pub threads: Option<usize>,
+    /// The index of the texture in `AssetsConfig::tex` to use for the sky.
+    pub sky_tex: Option<usize>,
+    /// Simulation ticks per second.
+    pub tick_rate: Option<f32>,
+    /// Sample distant walls and floors from smaller copies of their textures.
+    /// On by default.
+    pub mipmaps: Option<bool>,
+    /// How walls and floors are sampled from textures. Nearest by default.
+    #[serde(default)]
+    pub sampling: Sampling,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct MouseConfig {
+    /// Radians turned per unit of mouse motion.
+    pub sensitivity: f32,
+    /// Moving the mouse up makes the camera look down.
+    pub invert_y: bool,
+}
+
+impl Default for MouseConfig {
+    fn default() -> Self {
+        MouseConfig {
+            sensitivity: 0.003,
+            invert_y: false,
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -50,12 +116,106 @@ pub enum ConfigReadError {
     ParsingError(#[from] de::Error),
 }
 
+impl AssetsConfig {
+    /// Returns the names of the textures in `tex`. See `tex_name`.
+    pub fn tex_names(&self) -> Vec<String> {
+        self.tex.iter().map(tex_name).collect()
+    }
+}
+
+impl ScreenConfig {
+    /// Returns the resolution frames are rendered at in a window of size
+    /// `win_wd` x `win_ht`.
+    ///
+    /// If only one of `render_wd` and `render_ht` is set, the other one follows
+    /// the window's aspect ratio. If neither is, frames are rendered at the
+    /// window's size.
+    pub fn render_size(&self, win_wd: u32, win_ht: u32) -> (u32, u32) {
+        let (win_wd, win_ht) = (win_wd.max(1), win_ht.max(1));
+
+        match (self.render_wd, self.render_ht) {
+            (Some(wd), Some(ht)) => (wd, ht),
+            (Some(wd), None) => (wd, (wd * win_ht / win_wd).max(1)),
+            (None, Some(ht)) => ((ht * win_wd / win_ht).max(1), ht),
+            (None, None) => (win_wd, win_ht),
+        }
+    }
+}
+
+impl PlayerConfig {
+    /// Returns `radius` or 0.2 if it isn't set.
+    pub fn radius(&self) -> f32 {
+        self.radius.unwrap_or(0.2)
+    }
+}
+
+impl MiscConfig {
+    /// Returns `threads` or the available parallelism if it isn't set.
+    pub fn threads(&self) -> usize {
+        self.threads
+            .or_else(|| thread::available_parallelism().ok().map(usize::from))
+            .unwrap_or(1)
+    }
+}
+
 impl Config {
     pub fn read<P: AsRef<Path>>(path: P) -> Result<Config, io::Error> {
         let mut file = File::open(path)?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
 
-        Ok(toml::from_str(&contents)?)
+        let config: Config = toml::from_str(&contents)?;
+        let screen = &config.screen;
+
+        // There would be nothing to render into.
+        let sizes = [
+            Some(screen.wd),
+            Some(screen.ht),
+            screen.render_wd,
+            screen.render_ht,
+        ];
+        if sizes.contains(&Some(0)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Screen sizes must be greater than 0",
+            ));
+        }
+
+        // Their reciprocals are the lengths of ticks and frames.
+        let rates = [config.misc.tick_rate, screen.fps_cap];
+        if rates
+            .iter()
+            .flatten()
+            .any(|rate| !(rate.is_finite() && *rate > 0.0))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "tick_rate and fps_cap must be positive numbers",
+            ));
+        }
+
+        // The player has to fit through corridors 1 unit wide.
+        if !(0.0..0.5).contains(&config.player.radius()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Player radius must be at least 0 and less than 0.5",
+            ));
+        }
+
+        Ok(config)
+    }
+
+    /// Returns the texture ids given here for things drawn along with the map.
+    /// See `Map::validate`.
+    pub fn tex_users(&self) -> Vec<(TexUser, usize)> {
+        let mut users = vec![(TexUser::DefaultFloor, self.misc.floor_tex)];
+        users.extend(self.misc.sky_tex.map(|tex| (TexUser::Sky, tex)));
+        users.extend(
+            self.sprites
+                .iter()
+                .enumerate()
+                .map(|(i, sprite)| (TexUser::Sprite(i), sprite.tex as usize)),
+        );
+        users
     }
 }
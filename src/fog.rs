@@ -0,0 +1,53 @@
+use serde::Deserialize;
+
+/// Makes things fade towards `color` as they get farther from the camera.
+///
+/// Nothing closer than `start` is affected. Things farther than `end` are
+/// entirely of `color`. In between, the fade is linear in distance. A black fog
+/// works like light falling off with distance.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct Fog {
+    pub color: [u8; 3],
+    pub start: f32,
+    pub end: f32,
+}
+
+impl Fog {
+    /// Returns how much of the fog's color shows at perpendicular distance
+    /// `dist`. It lies in [0, 256] so that `blend` can stick to integers.
+    pub fn weight(&self, dist: f32) -> u32 {
+        let amount = (dist - self.start) / (self.end - self.start).max(f32::EPSILON);
+        (256.0 * amount.clamp(0.0, 1.0)) as u32
+    }
+
+    /// Mixes channel `c` of the fog's color into `val` as per `weight`.
+    pub fn blend(&self, val: u8, c: usize, weight: u32) -> u8 {
+        ((val as u32 * (256 - weight) + self.color[c] as u32 * weight) >> 8) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FOG: Fog = Fog {
+        color: [100, 0, 200],
+        start: 2.0,
+        end: 6.0,
+    };
+
+    #[test]
+    fn weight_grows_linearly_between_start_and_end() {
+        assert_eq!(FOG.weight(1.0), 0);
+        assert_eq!(FOG.weight(4.0), 128);
+        assert_eq!(FOG.weight(6.0), 256);
+        assert_eq!(FOG.weight(f32::INFINITY), 256);
+    }
+
+    #[test]
+    fn blend_fades_towards_color() {
+        assert_eq!(FOG.blend(50, 0, 0), 50);
+        assert_eq!(FOG.blend(50, 1, 128), 25);
+        assert_eq!(FOG.blend(50, 2, 256), 200);
+    }
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use png::{BitDepth, ColorType, Encoder};
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Represents an in-memory RGBA image which the `Raycaster` can render into.
+///
+/// This allows rendering without a window e.g when taking preview shots.
+pub struct Frame {
+    pub wd: u32,
+    pub ht: u32,
+    pub buf: Vec<u8>,
+}
+
+/// How a `Frame` is fit onto a differently sized surface.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scaling {
+    /// Scaled by the largest whole number which fits and centered. Every pixel
+    /// of the frame becomes an equally sized block. The rest is black.
+    #[default]
+    Integer,
+    /// Stretched to cover the whole surface. Pixels may end up unevenly sized.
+    Stretch,
+}
+
+#[derive(Debug, Error)]
+pub enum FrameSaveError {
+    #[error("Couldn't create image file")]
+    IoError(#[from] io::Error),
+    #[error("Error in encoding png")]
+    EncodingError(#[from] png::EncodingError),
+}
+
+impl Frame {
+    /// Creates a black `Frame` of the given dimensions.
+    pub fn new(wd: u32, ht: u32) -> Self {
+        Frame {
+            wd,
+            ht,
+            buf: vec![0; (4 * wd * ht) as usize],
+        }
+    }
+
+    /// Stretches the `Frame` to `wd` x `ht` into `dst`. Each pixel of `dst`
+    /// takes the color of the nearest pixel of the `Frame`, which keeps pixels
+    /// sharp.
+    ///
+    /// This is what `Scaling::Stretch` does. `Scaling::Integer` is left to
+    /// `pixels`.
+    pub fn upscale(&self, dst: &mut [u8], wd: u32, ht: u32) {
+        assert_eq!(wd * ht * 4, dst.len() as u32);
+
+        let src_xs: Vec<_> = (0..wd).map(|x| 4 * (x * self.wd / wd) as usize).collect();
+
+        for (y, row) in (0..ht).zip(dst.chunks_exact_mut(4 * wd as usize)) {
+            let src_y = y * self.ht / ht;
+            let src_row = &self.buf[(4 * self.wd * src_y) as usize..][..4 * self.wd as usize];
+
+            for (px, &src_x) in row.chunks_exact_mut(4).zip(&src_xs) {
+                px.copy_from_slice(&src_row[src_x..src_x + 4]);
+            }
+        }
+    }
+
+    /// Saves the `Frame` as an RGBA PNG at `path`.
+    pub fn save<P: AsRef<Path> + fmt::Debug>(&self, path: P) -> Result<(), FrameSaveError> {
+        info!("Saving frame to {:?}", path);
+
+        let file = File::create(path)?;
+        let mut encoder = Encoder::new(BufWriter::new(file), self.wd, self.ht);
+        encoder.set_color(ColorType::RGBA);
+        encoder.set_depth(BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.buf)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x1 frame with a red and a green pixel.
+    fn make_frame() -> Frame {
+        Frame {
+            wd: 2,
+            ht: 1,
+            buf: vec![255, 0, 0, 255, 0, 255, 0, 255],
+        }
+    }
+
+    /// Returns the red channel of each pixel of `buf`, row by row.
+    fn reds(buf: &[u8], wd: usize) -> Vec<Vec<u8>> {
+        buf.chunks(4 * wd)
+            .map(|row| row.chunks(4).map(|px| px[0]).collect())
+            .collect()
+    }
+
+    #[test]
+    fn upscale_fills_surface() {
+        let mut dst = vec![1; 3 * 2 * 4];
+        make_frame().upscale(&mut dst, 3, 2);
+
+        assert_eq!(reds(&dst, 3), [[255, 255, 0], [255, 255, 0]]);
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use serde::Deserialize;
+use winit::event::VirtualKeyCode;
+use winit_input_helper::WinitInputHelper;
+
+/// Something the player can do. Each action is triggered by one or more
+/// `Binding`s.
+///
+/// In `Config.toml`, actions are written in snake case like `move_forward`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    StrafeLeft,
+    StrafeRight,
+    TurnLeft,
+    TurnRight,
+    LookUp,
+    LookDown,
+    Crouch,
+    Use,
+    Fire,
+    ReleaseCursor,
+    Quit,
+}
+
+impl Action {
+    const NAMES: [(&'static str, Action); 13] = [
+        ("move_forward", Action::MoveForward),
+        ("move_backward", Action::MoveBackward),
+        ("strafe_left", Action::StrafeLeft),
+        ("strafe_right", Action::StrafeRight),
+        ("turn_left", Action::TurnLeft),
+        ("turn_right", Action::TurnRight),
+        ("look_up", Action::LookUp),
+        ("look_down", Action::LookDown),
+        ("crouch", Action::Crouch),
+        ("use", Action::Use),
+        ("fire", Action::Fire),
+        ("release_cursor", Action::ReleaseCursor),
+        ("quit", Action::Quit),
+    ];
+}
+
+// Actions are used as keys of TOML tables. Those can only be deserialized from
+// strings, so the derived (enum) deserialization doesn't work for them.
+impl TryFrom<String> for Action {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        Action::NAMES
+            .iter()
+            .find(|(action_name, _)| *action_name == name)
+            .map(|&(_, action)| action)
+            .ok_or_else(|| format!("unknown action `{}`", name))
+    }
+}
+
+/// A key or a mouse button.
+///
+/// In `Config.toml`, keys are written using the names of winit's
+/// `VirtualKeyCode` (like `"W"`, `"Left"` or `"LShift"`) and mouse buttons like
+/// `{ mouse = 0 }` (0 is the left button, 1 the right one and 2 the middle one).
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse { mouse: usize },
+}
+
+impl Binding {
+    fn held(self, input: &WinitInputHelper) -> bool {
+        match self {
+            Binding::Key(key) => input.key_held(key),
+            Binding::Mouse { mouse } => input.mouse_held(mouse),
+        }
+    }
+
+    fn pressed(self, input: &WinitInputHelper) -> bool {
+        match self {
+            Binding::Key(key) => input.key_pressed(key),
+            Binding::Mouse { mouse } => input.mouse_pressed(mouse),
+        }
+    }
+}
+
+/// Maps actions to the bindings which trigger them.
+#[derive(Debug)]
+pub struct Bindings {
+    map: HashMap<Action, Vec<Binding>>,
+}
+
+impl Bindings {
+    /// Returns the default bindings with the actions in `overrides` rebound.
+    /// Actions missing in `overrides` keep their default bindings. An empty
+    /// list unbinds an action.
+    pub fn new(overrides: &HashMap<Action, Vec<Binding>>) -> Self {
+        let mut bindings = Self::default();
+
+        for (action, binds) in overrides {
+            bindings.map.insert(*action, binds.clone());
+        }
+
+        bindings
+    }
+
+    /// Returns true if any binding of `action` is being held down.
+    pub fn held(&self, input: &WinitInputHelper, action: Action) -> bool {
+        self.get(action).iter().any(|binding| binding.held(input))
+    }
+
+    /// Returns true if any binding of `action` was pressed in the last step.
+    pub fn pressed(&self, input: &WinitInputHelper, action: Action) -> bool {
+        self.get(action)
+            .iter()
+            .any(|binding| binding.pressed(input))
+    }
+
+    pub fn get(&self, action: Action) -> &[Binding] {
+        self.map.get(&action).map_or(&[], Vec::as_slice)
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use Action::*;
+        use VirtualKeyCode as Key;
+
+        let defaults = [
+            (MoveForward, Key::W),
+            (MoveBackward, Key::S),
+            (StrafeLeft, Key::A),
+            (StrafeRight, Key::D),
+            (TurnLeft, Key::Left),
+            (TurnRight, Key::Right),
+            (LookUp, Key::Up),
+            (LookDown, Key::Down),
+            (Crouch, Key::C),
+            (Use, Key::E),
+            (Fire, Key::Space),
+            (ReleaseCursor, Key::Tab),
+            (Quit, Key::Escape),
+        ];
+
+        Bindings {
+            map: defaults
+                .iter()
+                .map(|&(action, key)| (action, vec![Binding::Key(key)]))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Controls {
+        controls: HashMap<Action, Vec<Binding>>,
+    }
+
+    #[test]
+    fn bindings_read_from_toml_override_defaults() {
+        let contents = r#"
+            [controls]
+            move_forward = ["Z", "Up"]
+            fire = [{ mouse = 0 }]
+            crouch = []
+        "#;
+        let controls: Controls = toml::from_str(contents).unwrap();
+        let bindings = Bindings::new(&controls.controls);
+
+        assert_eq!(
+            bindings.get(Action::MoveForward),
+            [
+                Binding::Key(VirtualKeyCode::Z),
+                Binding::Key(VirtualKeyCode::Up)
+            ]
+        );
+        assert_eq!(bindings.get(Action::Fire), [Binding::Mouse { mouse: 0 }]);
+        assert_eq!(bindings.get(Action::Crouch), []);
+        assert_eq!(
+            bindings.get(Action::StrafeLeft),
+            [Binding::Key(VirtualKeyCode::A)]
+        );
+    }
+
+    #[test]
+    fn unknown_actions_are_rejected() {
+        let contents = r#"
+            [controls]
+            jump = ["Space"]
+        "#;
+
+        assert!(toml::from_str::<Controls>(contents).is_err());
+    }
+
+    #[test]
+    fn unknown_key_names_are_rejected() {
+        let contents = r#"
+            [controls]
+            use = ["NotAKey"]
+        "#;
+
+        assert!(toml::from_str::<Controls>(contents).is_err());
+    }
+}
@@ -3,7 +3,12 @@ extern crate log;
 
 pub mod camera;
 pub mod config;
+pub mod fog;
+pub mod frame;
+pub mod input;
 pub mod map;
 pub mod primitive;
 pub mod raycaster;
+pub mod sprite;
 pub mod texture;
+pub mod timestep;
@@ -1,22 +1,43 @@
 #[macro_use]
 extern crate log;
 
-use std::{error::Error, f32::consts::PI, fmt, path::Path};
+use std::{
+    error::Error,
+    f32::consts::PI,
+    mem,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use native_dialog::{MessageDialog, MessageType};
 use pixels::{Pixels, SurfaceTexture};
 use raycaster::{
-    camera::Camera, config::Config, map::Map, primitive::Vec2, raycaster::Raycaster,
-    texture::Texture,
+    camera::Camera,
+    config::{Config, MouseConfig, PlayerConfig, ScreenConfig},
+    frame::{Frame, Scaling},
+    input::{Action, Bindings},
+    map::Map,
+    primitive::Vec2,
+    raycaster::{Raycaster, Scratch},
+    sprite::Sprite,
+    texture::TextureRegistry,
+    timestep::FixedTimestep,
 };
 use winit::{
     dpi::LogicalSize,
-    event::{Event, VirtualKeyCode},
+    event::{DeviceEvent, Event, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{Window, WindowBuilder},
 };
 use winit_input_helper::WinitInputHelper;
 
+/// How much the pitch changes per second while looking up or down.
+const LOOK_SPEED: f32 = 1.2;
+/// Max pitch in either direction. See `Camera::pitch`.
+const MAX_PITCH: f32 = 0.5;
+/// Eye height while crouching as a fraction of the standing eye height.
+const CROUCH_FACTOR: f32 = 0.6;
+
 fn main() {
     env_logger::init();
 
@@ -26,116 +47,387 @@ fn main() {
 
     let scr_wd = config.screen.wd;
     let scr_ht = config.screen.ht;
+    let tex_users = config.tex_users();
     let player = config.player;
     let wall_ht_scale = config.misc.wall_ht_scale.unwrap_or(1.0);
+    let max_dist = config.misc.max_dist.unwrap_or(100.0);
 
     let main_loop = EventLoop::new();
     let mut input = WinitInputHelper::new();
     let window = WindowBuilder::new()
         .with_title("Raycaster")
         .with_inner_size(LogicalSize::new(scr_wd as f64, scr_ht as f64))
-        .with_resizable(false)
+        .with_resizable(true)
         .build(&main_loop)
         .map_err(|e| error_dialog(e, "Couldn't create window"))
         .unwrap();
 
     debug!("Setup window");
 
-    let surf = SurfaceTexture::new(scr_wd, scr_ht, &window);
-    let mut pixels = Pixels::new(scr_wd, scr_ht, surf)
+    let mut screen = Screen::new(&window, &config.screen)
         .map_err(|e| error_dialog(e, "Couldn't setup drawing"))
         .unwrap();
 
     debug!("Setup pixels");
 
+    let eye_ht = player.eye_ht.unwrap_or(0.5);
     let mut camera = Camera::new(
         player.initial_pos,
         player.initial_dir,
         player.fov.unwrap_or(90.0),
     );
-    let map = Map::load(config.assets.map)
+    camera.eye_ht = eye_ht;
+    camera.set_aspect(screen.aspect());
+    let textures = TextureRegistry::load(&config.assets.tex)
+        .map_err(|e| error_dialog(e, "Couldn't load texture"))
+        .unwrap();
+    let mut map = Map::load(&config.assets.map, textures.names())
         .map_err(|e| error_dialog(e, "Couldn't load map file"))
         .unwrap();
-    let caster = Raycaster {
-        scr_wd,
-        scr_ht,
-        textures: load_textures(&config.assets.tex),
+    map.check(config.assets.tex.len(), player.initial_pos, &tex_users)
+        .map_err(|e| error_dialog(e, "Invalid map"))
+        .unwrap();
+    let mut caster = Raycaster {
+        scr_wd: screen.render_wd,
+        scr_ht: screen.render_ht,
+        textures: Arc::new(textures),
         floor_id: config.misc.floor_tex,
         wall_ht_scale,
+        max_dist,
+        threads: config.misc.threads(),
+        fog: config.fog,
+        sky_id: config.misc.sky_tex,
+        mipmaps: config.misc.mipmaps.unwrap_or(true),
+        sampling: config.misc.sampling,
+        scratch: Scratch::default(),
     };
+    let sprites = config.sprites;
 
     debug!("Ready to run");
 
+    let mut timestep = FixedTimestep::new(config.misc.tick_rate.unwrap_or(60.0), Instant::now());
+    let frame_time = config
+        .screen
+        .fps_cap
+        .map(|fps| Duration::from_secs_f32(1.0 / fps));
+    let mut prev_camera = camera.clone();
+    let mouse = config.mouse;
+    let screen_config = config.screen;
+    let bindings = Bindings::new(&config.controls);
+    let mut mouse_delta = (0.0, 0.0);
+    let mut grabbed = set_grab(&window, true);
+    let mut resized = false;
+    // When the last frame was asked for. Frames are due `frame_time` apart.
+    let mut last_frame = Instant::now();
+
+    window.request_redraw();
+
     main_loop.run(move |event, _, cflow| {
-        // Only run the loop when an event occurs. The only reason why anything
-        // should change is that the player moved i.e an event occured.
-        *cflow = ControlFlow::Wait;
+        match &event {
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } if grabbed => {
+                mouse_delta.0 += delta.0;
+                mouse_delta.1 += delta.1;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Focused(false),
+                ..
+            } => grabbed = set_grab(&window, false),
+            // Minimized windows have no size. There's nothing to draw then.
+            // Dragging a window's edge sends lots of these. They are only
+            // acted on once before the next frame is drawn.
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } if size.width > 0 && size.height > 0 => resized = true,
+            _ => {}
+        }
 
         if let Event::RedrawRequested(_) = event {
-            caster.render(&camera, &map, pixels.get_frame());
-            pixels
-                .render()
+            if mem::take(&mut resized) {
+                screen
+                    .resize(&window, &screen_config)
+                    .map_err(|e| error_dialog(e, "Couldn't setup drawing"))
+                    .unwrap();
+
+                caster.scr_wd = screen.render_wd;
+                caster.scr_ht = screen.render_ht;
+                camera.set_aspect(screen.aspect());
+                prev_camera.set_aspect(screen.aspect());
+            }
+
+            let view = prev_camera.lerp(&camera, timestep.alpha());
+            screen
+                .draw(&caster, &view, &map, &sprites)
                 .map_err(|e| error_dialog(e, "Couldn't draw frame"))
                 .unwrap();
+
+            // Without a cap, the next frame is asked for right away. Drawing
+            // waits for vsync. So frames are still paced by the display.
+            if frame_time.is_none() {
+                window.request_redraw();
+            }
         }
 
         if input.update(&event) {
-            if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
+            if bindings.pressed(&input, Action::Quit) || input.quit() {
                 info!("Quit event received");
                 *cflow = ControlFlow::Exit;
                 return;
             }
 
-            let mut moved = false;
-
-            if input.key_held(VirtualKeyCode::Left) {
-                camera.rotate_by(-player.speed);
-                moved = true;
-            }
+            // The click which grabs the cursor isn't meant to fire.
+            let was_grabbed = grabbed;
 
-            if input.key_held(VirtualKeyCode::Right) {
-                camera.rotate_by(player.speed);
-                moved = true;
+            if bindings.pressed(&input, Action::ReleaseCursor) && grabbed {
+                grabbed = set_grab(&window, false);
+            } else if input.mouse_pressed(0) && !grabbed {
+                grabbed = set_grab(&window, true);
             }
 
-            let mut offs = Vec2::new(0.0, 0.0);
+            // Mouse look isn't tied to the simulation. It is applied right
+            // away to both the cameras being interpolated between.
+            let (dx, dy) = mem::take(&mut mouse_delta);
+            mouse_look(&mut camera, dx as f32, dy as f32, &mouse);
+            mouse_look(&mut prev_camera, dx as f32, dy as f32, &mouse);
 
-            if input.key_held(VirtualKeyCode::W) {
-                offs += camera.dir * player.speed;
+            if bindings.pressed(&input, Action::Use) {
+                map.use_door(camera.pos, camera.dir);
             }
 
-            if input.key_held(VirtualKeyCode::S) {
-                offs -= camera.dir * player.speed;
+            if was_grabbed && grabbed && bindings.pressed(&input, Action::Fire) {
+                debug!("Fire");
             }
 
-            if input.key_held(VirtualKeyCode::A) {
-                offs -= camera.dir.rotated(PI / 2.0) * player.speed;
-            }
-            if input.key_held(VirtualKeyCode::D) {
-                offs += camera.dir.rotated(PI / 2.0) * player.speed;
-            }
+            let now = Instant::now();
 
-            if offs.len_squared() > 0.0 {
-                camera.pos = map.resolve_collisions(camera.pos, camera.pos + offs);
-                moved = true;
+            for _ in 0..timestep.advance(now) {
+                prev_camera = camera.clone();
+                tick(
+                    &input,
+                    &bindings,
+                    &player,
+                    eye_ht,
+                    &mut camera,
+                    &mut map,
+                    timestep.dt(),
+                );
             }
 
-            if moved {
-                window.request_redraw();
-            }
+            // Input can arrive much more often than frames are due. So frames
+            // are only drawn once `frame_time` has passed since the last one.
+            *cflow = match frame_time {
+                Some(frame_time) => {
+                    if now >= last_frame + frame_time {
+                        window.request_redraw();
+                        last_frame = now;
+                    }
+
+                    ControlFlow::WaitUntil(last_frame + frame_time)
+                }
+                None => ControlFlow::Wait,
+            };
         }
     });
 }
 
-fn load_textures<P: AsRef<Path> + fmt::Debug>(paths: &[P]) -> Vec<Texture> {
-    paths
-        .iter()
-        .map(|path| {
-            Texture::load(path)
-                .map_err(|e| error_dialog(e, "Couldn't load texture"))
-                .unwrap()
+/// The window's drawing surface and how frames get onto it.
+///
+/// `pixels` scales its buffer up to the window by whole numbers by itself. For
+/// stretching, its buffer covers the whole window instead. Frames are then
+/// rendered into `frame` first and scaled into the buffer.
+///
+/// `pixels` is only `None` while it is being set up again in `resize`.
+struct Screen {
+    pixels: Option<Pixels<Window>>,
+    frame: Option<Frame>,
+    render_wd: u32,
+    render_ht: u32,
+    buf_wd: u32,
+    buf_ht: u32,
+}
+
+impl Screen {
+    fn new(window: &Window, config: &ScreenConfig) -> Result<Self, pixels::Error> {
+        let ((render_wd, render_ht), (buf_wd, buf_ht)) = Screen::sizes(window, config);
+        let pixels = Screen::pixels(window, buf_wd, buf_ht)?;
+        let frame = if (buf_wd, buf_ht) != (render_wd, render_ht) {
+            Some(Frame::new(render_wd, render_ht))
+        } else {
+            None
+        };
+
+        debug!(
+            "Rendering at {}x{} into a {}x{} buffer",
+            render_wd, render_ht, buf_wd, buf_ht
+        );
+
+        Ok(Screen {
+            pixels: Some(pixels),
+            frame,
+            render_wd,
+            render_ht,
+            buf_wd,
+            buf_ht,
         })
-        .collect()
+    }
+
+    /// Adapts to `window` having been resized. `pixels` is only set up again if
+    /// the size of its buffer has to change.
+    fn resize(&mut self, window: &Window, config: &ScreenConfig) -> Result<(), pixels::Error> {
+        let (render, buf) = Screen::sizes(window, config);
+
+        if render == (self.render_wd, self.render_ht) && buf == (self.buf_wd, self.buf_ht) {
+            let win_size = window.inner_size();
+            let pixels = self.pixels.as_mut().expect("pixels is set up");
+            pixels.resize(win_size.width, win_size.height);
+        } else {
+            // Some backends refuse to give a window a second swap chain. So the
+            // old one has to be gone before the new one is set up.
+            self.pixels = None;
+            *self = Screen::new(window, config)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets up `pixels` with a `wd` x `ht` buffer covering the whole `window`.
+    fn pixels(window: &Window, wd: u32, ht: u32) -> Result<Pixels<Window>, pixels::Error> {
+        let win_size = window.inner_size();
+        let surf = SurfaceTexture::new(win_size.width, win_size.height, window);
+        Pixels::new(wd, ht, surf)
+    }
+
+    /// Returns the resolution to render at and the size of `pixels`'s buffer.
+    /// Without a configured render size, frames are rendered at the window's
+    /// physical size. `pixels` only scales by whole numbers. So frames at the
+    /// logical size wouldn't fill windows with fractional scale factors.
+    fn sizes(window: &Window, config: &ScreenConfig) -> ((u32, u32), (u32, u32)) {
+        let win_size = window.inner_size();
+        let render = config.render_size(win_size.width, win_size.height);
+
+        let buf = match config.scaling {
+            Scaling::Integer => render,
+            Scaling::Stretch => (win_size.width.max(1), win_size.height.max(1)),
+        };
+
+        (render, buf)
+    }
+
+    /// Returns the aspect ratio of frames as they appear in the window.
+    fn aspect(&self) -> f32 {
+        match self.frame {
+            Some(_) => self.buf_wd as f32 / self.buf_ht as f32,
+            None => self.render_wd as f32 / self.render_ht as f32,
+        }
+    }
+
+    /// Renders a frame with `caster` and shows it in the window.
+    fn draw(
+        &mut self,
+        caster: &Raycaster,
+        camera: &Camera,
+        map: &Map,
+        sprites: &[Sprite],
+    ) -> Result<(), pixels::Error> {
+        let pixels = self.pixels.as_mut().expect("pixels is set up");
+
+        match &mut self.frame {
+            Some(frame) => {
+                caster.render(camera, map, sprites, &mut frame.buf);
+                let buf = pixels.get_frame();
+                frame.upscale(buf, self.buf_wd, self.buf_ht);
+            }
+            None => caster.render(camera, map, sprites, pixels.get_frame()),
+        }
+
+        pixels.render()
+    }
+}
+
+/// Turns and pitches `camera` for mouse motion of (`dx`, `dy`).
+fn mouse_look(camera: &mut Camera, dx: f32, dy: f32, mouse: &MouseConfig) {
+    let dy = if mouse.invert_y { dy } else { -dy };
+
+    camera.rotate_by(dx * mouse.sensitivity);
+    camera.pitch = (camera.pitch + dy * mouse.sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+}
+
+/// Grabs and hides the cursor (or releases it) so that the mouse can be used
+/// for looking around. Returns whether the cursor is grabbed.
+fn set_grab(window: &Window, grab: bool) -> bool {
+    if let Err(e) = window.set_cursor_grab(grab) {
+        warn!("Couldn't change cursor grab: {}", e);
+        window.set_cursor_visible(true);
+        return false;
+    }
+
+    window.set_cursor_visible(!grab);
+    grab
+}
+
+/// Advances the simulation by `dt` seconds. Held actions move the player with
+/// speeds given in units (or radians) per second.
+fn tick(
+    input: &WinitInputHelper,
+    bindings: &Bindings,
+    player: &PlayerConfig,
+    eye_ht: f32,
+    camera: &mut Camera,
+    map: &mut Map,
+    dt: f32,
+) {
+    map.update(dt);
+
+    let turn = player.turn_speed.unwrap_or(2.5) * dt;
+
+    if bindings.held(input, Action::TurnLeft) {
+        camera.rotate_by(-turn);
+    }
+
+    if bindings.held(input, Action::TurnRight) {
+        camera.rotate_by(turn);
+    }
+
+    if bindings.held(input, Action::LookUp) {
+        camera.pitch = (camera.pitch + LOOK_SPEED * dt).min(MAX_PITCH);
+    }
+
+    if bindings.held(input, Action::LookDown) {
+        camera.pitch = (camera.pitch - LOOK_SPEED * dt).max(-MAX_PITCH);
+    }
+
+    camera.eye_ht = if bindings.held(input, Action::Crouch) {
+        eye_ht * CROUCH_FACTOR
+    } else {
+        eye_ht
+    };
+
+    let mut vel = Vec2::new(0.0, 0.0);
+
+    if bindings.held(input, Action::MoveForward) {
+        vel += camera.dir;
+    }
+
+    if bindings.held(input, Action::MoveBackward) {
+        vel -= camera.dir;
+    }
+
+    if bindings.held(input, Action::StrafeLeft) {
+        vel -= camera.dir.rotated(PI / 2.0);
+    }
+
+    if bindings.held(input, Action::StrafeRight) {
+        vel += camera.dir.rotated(PI / 2.0);
+    }
+
+    // Moving diagonally shouldn't be faster.
+    if vel.len_squared() > 0.0 {
+        let offs = vel.normalized() * player.speed * dt;
+        camera.pos = map.resolve_collisions(camera.pos, camera.pos + offs, player.radius());
+    }
 }
 
 fn error_dialog<T: Error + Sized>(e: T, title: &str) -> T {
@@ -0,0 +1,96 @@
+use super::{Map, TexId};
+use crate::primitive::Vec2;
+
+/// How much of a door opens (or closes) per second.
+const DOOR_SPEED: f32 = 1.0;
+
+/// How far in front of the player a door can be to be used.
+const DOOR_REACH: f32 = 1.0;
+
+/// Represents a door which slides open sideways.
+///
+/// A door is a thin panel in the middle of its cell. If `in_ns_dir` is true, it
+/// runs along the north-south axis (i.e at `x + 0.5` of its cell). Otherwise it
+/// runs along the east-west axis (i.e at `y + 0.5`).
+///
+/// The walls on the sides of a door's cell use `frame_tex` for their faces
+/// which are next to the door.
+#[derive(Clone, Debug)]
+pub struct Door {
+    pub tex: TexId,
+    pub frame_tex: TexId,
+    pub in_ns_dir: bool,
+    /// How far the door has slid open. 0 means closed and 1 means fully open.
+    pub openness: f32,
+    /// Whether the door is moving towards being open or towards being closed.
+    pub opening: bool,
+}
+
+impl Door {
+    /// Creates a closed door.
+    pub fn new(tex: TexId, frame_tex: TexId, in_ns_dir: bool) -> Self {
+        Door {
+            tex,
+            frame_tex,
+            in_ns_dir,
+            openness: 0.0,
+            opening: false,
+        }
+    }
+
+    /// Only fully open doors can be walked through.
+    pub fn is_open(&self) -> bool {
+        self.openness >= 1.0
+    }
+
+    fn update(&mut self, dt: f32) {
+        let delta = if self.opening { dt } else { -dt };
+        self.openness = (self.openness + DOOR_SPEED * delta).clamp(0.0, 1.0);
+    }
+}
+
+impl Map {
+    /// Advances the doors which are opening or closing by `dt` seconds.
+    pub fn update(&mut self, dt: f32) {
+        for door in self.doors.iter_mut().flatten() {
+            door.update(dt);
+        }
+    }
+
+    /// Opens (or closes) the door right in front of a player standing at `pos`
+    /// and facing `dir`. Returns false if there is no such door.
+    ///
+    /// A door isn't closed if the player is standing in its cell.
+    pub fn use_door(&mut self, pos: Vec2, dir: Vec2) -> bool {
+        let target = pos + DOOR_REACH * dir;
+
+        let idx = match self.cell_idx(target.x, target.y) {
+            Some(idx) => idx,
+            None => return false,
+        };
+
+        let in_door_cell = self.cell_idx(pos.x, pos.y) == Some(idx);
+
+        match &mut self.doors[idx] {
+            Some(door) if door.opening && in_door_cell => false,
+            Some(door) => {
+                door.opening = !door.opening;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Decides whether the door at (`x`, `y`) should run along the north-south
+    /// axis. Doors run between the walls on their sides. If there are walls on
+    /// both the east and west sides, it runs along the east-west axis.
+    pub fn door_in_ns_dir(&self, x: usize, y: usize) -> bool {
+        let is_wall =
+            |x: usize, y: usize| x < self.wd && y < self.ht && self.grid[self.wd * y + x].is_some();
+
+        let walls_ew = x > 0 && is_wall(x - 1, y) && is_wall(x + 1, y);
+        let walls_ns = y > 0 && is_wall(x, y - 1) && is_wall(x, y + 1);
+
+        walls_ns && !walls_ew
+    }
+}
@@ -0,0 +1,189 @@
+use serde::Deserialize;
+
+use super::{Face, Map};
+use crate::primitive::{Ray2, Vec2};
+
+/// Light which leaves texels unchanged. See `LightVal`.
+pub const FULL_LIGHT: u16 = 256;
+
+/// Max light as a multiple of `FULL_LIGHT`. Places near bright lights can get
+/// brighter than their textures, but only this much.
+const MAX_LIGHT: f32 = 2.0;
+
+/// How far in front of a face its light is sampled. This keeps the sample from
+/// being inside the wall.
+const SAMPLE_OFFS: f32 = 0.01;
+
+/// Light falling on something. Each channel scales the same channel of a texel.
+/// `FULL_LIGHT` leaves it unchanged.
+pub type LightVal = [u16; 3];
+
+/// A point light. It lights everything within `radius` which it can see. Its
+/// light falls off linearly with distance.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct Light {
+    pub pos: Vec2,
+    pub color: [u8; 3],
+    pub radius: f32,
+}
+
+/// Light baked for each cell of a `Map`.
+///
+/// Empty cells get a single value for their floor and ceiling (and for sprites
+/// standing in them). Cells with walls or doors get a value for each face.
+#[derive(Debug)]
+pub struct Lightmap {
+    floor: Vec<LightVal>,
+    faces: Vec<[LightVal; 4]>,
+}
+
+impl Map {
+    /// Bakes `lights` and `ambient` into `lightmap`.
+    ///
+    /// # Overall idea
+    ///
+    /// Light is sampled at the center of each floor cell and just in front of
+    /// the center of each face of walls and doors. A light reaches a sample if
+    /// a ray from the light to the sample doesn't hit a wall (or a closed door)
+    /// on the way.
+    ///
+    /// The lightmap isn't updated by itself. Doors opening later don't let any
+    /// more light through.
+    pub fn bake_lightmap(&mut self) {
+        let mut floor = vec![[0; 3]; self.wd * self.ht];
+        let mut faces = vec![[[0; 3]; 4]; self.wd * self.ht];
+
+        for idx in 0..self.wd * self.ht {
+            let center = Vec2::new((idx % self.wd) as f32 + 0.5, (idx / self.wd) as f32 + 0.5);
+
+            // Doors lie in the middle of their cells. Walls fill them.
+            let half_wd = match (self.grid[idx], &self.doors[idx]) {
+                (Some(_), _) => 0.5,
+                (None, Some(_)) => 0.0,
+                (None, None) => {
+                    floor[idx] = self.light_at(center);
+                    continue;
+                }
+            };
+
+            for face in [Face::North, Face::East, Face::South, Face::West] {
+                let sample = center + (half_wd + SAMPLE_OFFS) * face.normal();
+                faces[idx][face as usize] = self.light_at(sample);
+            }
+        }
+
+        self.lightmap = Some(Lightmap { floor, faces });
+    }
+
+    /// Returns the light on the floor (and ceiling) of the cell at `idx`.
+    pub fn floor_light(&self, idx: usize) -> LightVal {
+        self.lightmap
+            .as_ref()
+            .map_or([FULL_LIGHT; 3], |lightmap| lightmap.floor[idx])
+    }
+
+    /// Returns the light on `face` of the wall (or door) at `idx`.
+    pub fn face_light(&self, idx: usize, face: Face) -> LightVal {
+        self.lightmap.as_ref().map_or([FULL_LIGHT; 3], |lightmap| {
+            lightmap.faces[idx][face as usize]
+        })
+    }
+
+    /// Adds up the light reaching `pos`.
+    fn light_at(&self, pos: Vec2) -> LightVal {
+        let mut res = [0.0; 3];
+
+        for (c, val) in res.iter_mut().enumerate() {
+            *val = self.ambient[c] as f32;
+        }
+
+        for light in &self.lights {
+            let dist = (pos - light.pos).len();
+
+            if dist >= light.radius || !self.can_see(light.pos, pos, dist) {
+                continue;
+            }
+
+            let falloff = 1.0 - dist / light.radius;
+
+            for (c, val) in res.iter_mut().enumerate() {
+                *val += light.color[c] as f32 * falloff;
+            }
+        }
+
+        let scale = FULL_LIGHT as f32 / 255.0;
+        let max = MAX_LIGHT * FULL_LIGHT as f32;
+
+        [
+            (res[0] * scale).min(max) as u16,
+            (res[1] * scale).min(max) as u16,
+            (res[2] * scale).min(max) as u16,
+        ]
+    }
+
+    /// Returns true if nothing blocks the way from `from` to `to`. `dist` is
+    /// the distance between them.
+    fn can_see(&self, from: Vec2, to: Vec2, dist: f32) -> bool {
+        if dist <= f32::EPSILON {
+            return true;
+        }
+
+        let ray = Ray2::new(from, (to - from) / dist);
+        self.intersect(&ray, dist).is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A map whose middle column has walls at (2, 1) and (2, 2). A red light is
+    /// to their west.
+    fn make_map() -> Map {
+        let mut grid = vec![Some(0); 25];
+        for idx in [6, 8, 11, 13, 16, 17, 18] {
+            grid[idx] = None;
+        }
+        grid[12] = Some(1);
+
+        let mut map = Map::new(5, 5, grid);
+        map.ambient = [0, 0, 0];
+        map.lights = vec![Light {
+            pos: Vec2::new(1.5, 2.5),
+            color: [255, 0, 0],
+            radius: 4.0,
+        }];
+        map.bake_lightmap();
+        map
+    }
+
+    #[test]
+    fn bake_lightmap_lights_cells_in_view() {
+        let map = make_map();
+
+        assert_eq!(map.floor_light(11), [FULL_LIGHT, 0, 0]);
+
+        // The light is 1 unit away from the center of (1, 3).
+        let light = map.floor_light(16);
+        assert!(light[0].abs_diff(FULL_LIGHT * 3 / 4) <= 1);
+        assert_eq!(light[1..], [0, 0]);
+    }
+
+    #[test]
+    fn bake_lightmap_doesnt_light_through_walls() {
+        let map = make_map();
+
+        assert_eq!(map.floor_light(13), [0, 0, 0]);
+        assert_eq!(map.floor_light(18), [0, 0, 0]);
+        assert!(map.face_light(12, Face::West)[0] > 0);
+        assert_eq!(map.face_light(12, Face::East), [0, 0, 0]);
+    }
+
+    #[test]
+    fn map_without_lightmap_is_fully_lit() {
+        let map = Map::new(1, 1, vec![None]);
+
+        assert_eq!(map.floor_light(0), [FULL_LIGHT; 3]);
+        assert_eq!(map.face_light(0, Face::North), [FULL_LIGHT; 3]);
+    }
+}
@@ -1,12 +1,23 @@
+mod door;
+mod light;
 mod reader;
+mod reader_v2;
+mod validate;
 
 use std::fmt;
 use std::path::Path;
 
 use crate::primitive::{Ray2, Vec2};
+pub use door::Door;
+pub use light::{Light, LightVal, Lightmap, FULL_LIGHT};
 use reader::{read_map, MapReadError};
+pub use validate::{InvalidMapError, Layer, MapIssue, Severity, TexUser};
 
-pub type TexId = u8;
+/// Max distance moved in one step by `Map::resolve_collisions`. It must be less
+/// than the width of a cell.
+const MAX_STEP: f32 = 0.5;
+
+pub type TexId = u16;
 
 /// Represents a map in which the player can run around.
 pub struct Map {
@@ -16,6 +27,61 @@ pub struct Map {
     /// cell is empty. If it is `Some(tex)`, then the cell has a wall having a
     /// texture of id `tex`.
     pub grid: Vec<Option<TexId>>,
+    /// Textures of the faces of the wall in each cell, indexed by `Face`. Walls
+    /// which are `None` here show their texture in `grid` on every face.
+    pub face_tex: Vec<Option<[TexId; 4]>>,
+    /// Texture of the floor of each cell. `None` means that the renderer's
+    /// default floor texture is used.
+    pub floor: Vec<Option<TexId>>,
+    /// Texture of the ceiling of each cell. `None` means that the renderer's
+    /// default floor texture is used.
+    pub ceil: Vec<Option<TexId>>,
+    /// The door in each cell. Cells with doors are `None` in `grid`.
+    pub doors: Vec<Option<Door>>,
+    /// Height of the wall (or door) in each cell. Walls are 1 unit tall by
+    /// default (before scaling by the renderer's `wall_ht_scale`).
+    pub heights: Vec<f32>,
+    /// Whether each cell is open to the sky. Outdoor cells show the sky instead
+    /// of their ceiling (if the renderer has a sky texture).
+    pub outdoors: Vec<bool>,
+    pub lights: Vec<Light>,
+    /// Light which reaches everywhere, even where no light does.
+    pub ambient: [u8; 3],
+    /// Light baked from `lights` by `bake_lightmap`. Everything is fully lit
+    /// if it is `None`.
+    pub lightmap: Option<Lightmap>,
+}
+
+/// A side of a cell. North is towards -ve y and west is towards -ve x.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Face {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Face {
+    /// Returns the face which is hit by a ray going along `dir`. `in_ns_dir`
+    /// tells whether the face runs along the north-south axis.
+    pub fn hit_by(dir: Vec2, in_ns_dir: bool) -> Self {
+        match (in_ns_dir, dir.x > 0.0, dir.y > 0.0) {
+            (true, true, _) => Face::West,
+            (true, false, _) => Face::East,
+            (false, _, true) => Face::North,
+            (false, _, false) => Face::South,
+        }
+    }
+
+    /// Returns the unit vector pointing out of the face.
+    pub fn normal(self) -> Vec2 {
+        match self {
+            Face::North => Vec2::new(0.0, -1.0),
+            Face::East => Vec2::new(1.0, 0.0),
+            Face::South => Vec2::new(0.0, 1.0),
+            Face::West => Vec2::new(-1.0, 0.0),
+        }
+    }
 }
 
 /// Info about the point where a ray intersected a wall.
@@ -24,45 +90,183 @@ pub struct Intersection {
     pub pos: Vec2,
     pub tex: TexId,
     pub in_ns_dir: bool,
+    /// The horizontal texture co-ordinate of `pos`. It lies in [0, 1).
+    pub tex_x: f32,
+    /// Index of the cell whose wall (or door) was hit.
+    pub idx: usize,
+    /// The face of the wall (or door) which was hit.
+    pub face: Face,
+    /// Height of the wall (or door) which was hit.
+    pub ht: f32,
+    /// Where the ray leaves the wall's cell. It is the same as `pos` for doors
+    /// since they have no thickness.
+    pub exit: Vec2,
+}
+
+/// Iterator over the walls hit by a ray, from the nearest to the farthest. See
+/// `Map::hits`.
+pub struct Hits<'a> {
+    map: &'a Map,
+    ray: &'a Ray2,
+    /// Signs of the components of the ray's direction.
+    dir: Vec2,
+    step_ew: Vec2,
+    step_ns: Vec2,
+    /// Potential intersection position with a EW wall.
+    pos_ew: Vec2,
+    /// Potential intersection position with a NS wall.
+    pos_ns: Vec2,
+    max_dist_squared: f32,
+    /// The cell the ray is currently passing through.
+    prev_idx: Option<usize>,
+    started: bool,
+    done: bool,
 }
 
 impl Map {
-    /// Loads a map from at `path`.
-    pub fn load<P: AsRef<Path> + fmt::Debug>(path: P) -> Result<Self, MapReadError> {
-        read_map(path)
+    /// Creates an indoor `Map` without doors whose floor and ceiling use the
+    /// default texture.
+    pub fn new(wd: usize, ht: usize, grid: Vec<Option<TexId>>) -> Self {
+        Map {
+            wd,
+            ht,
+            grid,
+            face_tex: vec![None; wd * ht],
+            floor: vec![None; wd * ht],
+            ceil: vec![None; wd * ht],
+            doors: vec![None; wd * ht],
+            heights: vec![1.0; wd * ht],
+            outdoors: vec![false; wd * ht],
+            lights: Vec::new(),
+            ambient: [255; 3],
+            lightmap: None,
+        }
+    }
+
+    /// Loads a map from at `path`. `tex_names` is used to resolve the names of
+    /// textures in v2 maps.
+    pub fn load<P: AsRef<Path> + fmt::Debug>(
+        path: P,
+        tex_names: &[String],
+    ) -> Result<Self, MapReadError> {
+        read_map(path, tex_names)
     }
 
-    /// Goes from `old_pos` to `new_pos` while staying out of walls.
+    /// Goes from `old_pos` to `new_pos` while keeping a circle of `radius`
+    /// around the position out of walls.
     ///
     /// It works by simply ignoring the components of displacement which cause
     /// that issue. This allows us to retain the other harmless components. It
     /// allows us to slide along walls.
-    pub fn resolve_collisions(&self, old_pos: Vec2, new_pos: Vec2) -> Vec2 {
-        let old_idx_x = old_pos.x as usize;
-        let old_idx_y = old_pos.y as usize;
-        let new_idx_x = new_pos.x as usize;
-        let new_idx_y = new_pos.y as usize;
-
+    ///
+    /// Long displacements are split into steps no longer than `MAX_STEP`.
+    /// Otherwise a fast moving player could jump over a wall entirely.
+    ///
+    /// Positions outside the map are treated like walls. So the player can't
+    /// walk out of a map which isn't closed.
+    pub fn resolve_collisions(&self, old_pos: Vec2, new_pos: Vec2, radius: f32) -> Vec2 {
+        let steps = ((new_pos - old_pos).len() / MAX_STEP).ceil().max(1.0) as u32;
         let mut res = old_pos;
+        // Once blocked along an axis, later steps along it are skipped. They
+        // could otherwise land beyond the wall.
+        let (mut blocked_x, mut blocked_y) = (false, false);
+
+        for i in 1..=steps {
+            let target = if i == steps {
+                new_pos
+            } else {
+                old_pos + (new_pos - old_pos) * (i as f32 / steps as f32)
+            };
+
+            // Going along x won't cause a collision.
+            if !blocked_x && self.can_move(res, Vec2::new(target.x, res.y), radius) {
+                res.x = target.x;
+            } else {
+                blocked_x = true;
+            }
 
-        // Going along x won't cause a collision.
-        if self.grid[self.wd * old_idx_y + new_idx_x].is_none() {
-            res.x = new_pos.x;
+            // Going along y won't cause a collision.
+            if !blocked_y && self.can_move(res, Vec2::new(res.x, target.y), radius) {
+                res.y = target.y;
+            } else {
+                blocked_y = true;
+            }
+        }
+
+        res
+    }
+
+    /// Returns true if a circle of `radius` can go from `from` to `to`.
+    ///
+    /// A circle which already overlaps walls (say because a door closed on it)
+    /// may move as long as it overlaps them less. This way it doesn't get stuck.
+    fn can_move(&self, from: Vec2, to: Vec2, radius: f32) -> bool {
+        match (self.overlap(to, radius), self.overlap(from, radius)) {
+            (None, _) => true,
+            (Some(new), Some(old)) => new < old,
+            (Some(_), None) => false,
         }
+    }
+
+    /// Returns how deep a circle of `radius` at `pos` goes into the walls
+    /// around it. Returns `None` if it doesn't touch any wall.
+    fn overlap(&self, pos: Vec2, radius: f32) -> Option<f32> {
+        let mut res = None;
+
+        for y in (pos.y - radius).floor() as i64..=(pos.y + radius).floor() as i64 {
+            for x in (pos.x - radius).floor() as i64..=(pos.x + radius).floor() as i64 {
+                if !self.is_blocked(x as f32, y as f32) {
+                    continue;
+                }
 
-        // Going along y won't cause a collision.
-        if self.grid[self.wd * new_idx_y + old_idx_x].is_none() {
-            res.y = new_pos.y;
+                // The point of the cell which is closest to the circle's center.
+                let closest = Vec2::new(
+                    pos.x.clamp(x as f32, (x + 1) as f32),
+                    pos.y.clamp(y as f32, (y + 1) as f32),
+                );
+                let depth = radius - (pos - closest).len();
+
+                if depth >= 0.0 && res.is_none_or(|res| depth > res) {
+                    res = Some(depth);
+                }
+            }
         }
 
         res
     }
 
+    /// Returns the index in `grid` of the cell containing (`x`, `y`). Returns
+    /// `None` if the position lies outside the map.
+    pub fn cell_idx(&self, x: f32, y: f32) -> Option<usize> {
+        if x >= 0.0 && y >= 0.0 && x < self.wd as f32 && y < self.ht as f32 {
+            Some(self.wd * y as usize + x as usize)
+        } else {
+            None
+        }
+    }
+
+    fn is_blocked(&self, x: f32, y: f32) -> bool {
+        self.cell_idx(x, y).is_none_or(|idx| {
+            self.grid[idx].is_some() || self.doors[idx].as_ref().is_some_and(|door| !door.is_open())
+        })
+    }
+
     /// Calculates information about the point of intersection of `ray` with a
     /// wall in `self.map`.
     ///
-    /// An intersection point always exists because all maps in this demo have
-    /// to be closed i.e no ray can go on infinitely without hitting a wall :)
+    /// Returns `None` if `ray` leaves the map or travels farther than `max_dist`
+    /// without hitting a wall. This happens with maps which aren't closed.
+    ///
+    /// See `hits` for how it's done.
+    pub fn intersect(&self, ray: &Ray2, max_dist: f32) -> Option<Intersection> {
+        self.hits(ray, max_dist).next()
+    }
+
+    /// Returns the walls (and doors) hit by `ray`, from the nearest to the
+    /// farthest, till it leaves the map or travels farther than `max_dist`.
+    ///
+    /// Walls of different heights don't hide each other completely. So the
+    /// renderer keeps going past the first wall to find the ones behind it.
     ///
     /// # Overall idea
     ///
@@ -83,7 +287,13 @@ impl Map {
     /// have a separation of `step_ew`. By the way, _ns_ means north-south and
     /// _ew_ means east-west. All points having integral x-coordinates hit the
     /// face of a wall parallel to the north-south axis).
-    pub fn intersect(&self, ray: &Ray2) -> Intersection {
+    ///
+    /// Doors lie in the middle of their cells. So when the ray enters a cell
+    /// with a door, we check where it crosses the door's line separately.
+    ///
+    /// After a hit, generating points simply goes on from where it stopped. The
+    /// next point is where the ray leaves the wall's cell.
+    pub fn hits<'a>(&'a self, ray: &'a Ray2, max_dist: f32) -> Hits<'a> {
         let tan = ray.dir.y / ray.dir.x;
         let cot = 1.0 / tan;
         let dir = Vec2::new(ray.dir.x.signum(), ray.dir.y.signum());
@@ -91,8 +301,7 @@ impl Map {
         let step_ew = Vec2::new(cot.abs() * dir.x, dir.y);
         let step_ns = Vec2::new(dir.x, tan.abs() * dir.y);
 
-        // Potential intersection position with a EW wall.
-        let mut pos_ew = if dir.y > 0.0 {
+        let pos_ew = if dir.y > 0.0 {
             let shift = ray.pos.y.ceil() - ray.pos.y;
             Vec2::new(ray.pos.x + shift * cot, ray.pos.y.ceil())
         } else {
@@ -100,8 +309,7 @@ impl Map {
             Vec2::new(ray.pos.x + shift * cot, ray.pos.y.floor())
         };
 
-        // Potential intersection position with a NS wall.
-        let mut pos_ns = if dir.x > 0.0 {
+        let pos_ns = if dir.x > 0.0 {
             let shift = ray.pos.x.ceil() - ray.pos.x;
             Vec2::new(ray.pos.x.ceil(), ray.pos.y + shift * tan)
         } else {
@@ -109,38 +317,184 @@ impl Map {
             Vec2::new(ray.pos.x.floor(), ray.pos.y + shift * tan)
         };
 
-        loop {
-            // The next EW point is closer than the next NS point.
-            if (pos_ew.x * dir.x) < (pos_ns.x as f32 * dir.x) {
-                let res = pos_ew;
-                pos_ew += step_ew;
-
-                let idx_x = res.x as usize;
-                let idx_y = if dir.y > 0.0 { res.y } else { res.y - 1.0 } as usize;
-
-                if let Some(tex) = self.grid[self.wd * idx_y + idx_x] {
-                    return Intersection {
-                        pos: res,
-                        tex,
-                        in_ns_dir: false,
-                    };
+        Hits {
+            map: self,
+            ray,
+            dir,
+            step_ew,
+            step_ns,
+            pos_ew,
+            pos_ns,
+            max_dist_squared: max_dist * max_dist,
+            prev_idx: self.cell_idx(ray.pos.x, ray.pos.y),
+            started: false,
+            done: false,
+        }
+    }
+
+    /// Calculates where `ray` hits the door in the cell at `idx` (if any) given
+    /// that the ray enters the cell at `entry`.
+    ///
+    /// The ray misses the door if it leaves the cell before reaching the door's
+    /// line or if it passes through the part of the door which has slid open.
+    fn intersect_door(&self, ray: &Ray2, entry: Vec2, idx: usize) -> Option<Intersection> {
+        let door = self.doors[idx].as_ref()?;
+
+        let cell = Vec2::new((idx % self.wd) as f32, (idx / self.wd) as f32);
+
+        let shift = if door.in_ns_dir {
+            (cell.x + 0.5 - entry.x) / ray.dir.x
+        } else {
+            (cell.y + 0.5 - entry.y) / ray.dir.y
+        };
+
+        if shift.is_nan() || shift < 0.0 {
+            return None;
+        }
+
+        let pos = entry + shift * ray.dir;
+        let tex_x = if door.in_ns_dir {
+            pos.y - cell.y
+        } else {
+            pos.x - cell.x
+        };
+
+        if tex_x < door.openness || tex_x >= 1.0 {
+            return None;
+        }
+
+        Some(Intersection {
+            pos,
+            tex: door.tex,
+            in_ns_dir: door.in_ns_dir,
+            tex_x: tex_x - door.openness,
+            idx,
+            face: Face::hit_by(ray.dir, door.in_ns_dir),
+            ht: self.heights[idx],
+            exit: pos,
+        })
+    }
+
+    /// Returns the height of the tallest wall (or door).
+    pub fn max_ht(&self) -> f32 {
+        (0..self.wd * self.ht)
+            .filter(|&idx| self.grid[idx].is_some() || self.doors[idx].is_some())
+            .map(|idx| self.heights[idx])
+            .fold(0.0, f32::max)
+    }
+}
+
+impl Hits<'_> {
+    /// Returns the next point with an integral x or y coordinate. Also returns
+    /// the cell which the ray enters there and whether the point is on a NS
+    /// face.
+    fn advance(&mut self) -> (Vec2, Option<usize>, bool) {
+        let map = self.map;
+        let dir = self.dir;
+
+        // The next EW point is closer than the next NS point.
+        if (self.pos_ew.x * dir.x) < (self.pos_ns.x * dir.x) {
+            let res = self.pos_ew;
+            self.pos_ew += self.step_ew;
+
+            let idx_y = if dir.y > 0.0 { res.y } else { res.y - 1.0 };
+            (res, map.cell_idx(res.x, idx_y), false)
+        } else {
+            let res = self.pos_ns;
+            self.pos_ns += self.step_ns;
+
+            let idx_x = if dir.x > 0.0 { res.x } else { res.x - 1.0 };
+            (res, map.cell_idx(idx_x, res.y), true)
+        }
+    }
+
+    /// Returns whether `pos` is no farther from the ray's origin than the max
+    /// distance.
+    fn in_reach(&self, pos: Vec2) -> bool {
+        (pos - self.ray.pos).len_squared() <= self.max_dist_squared
+    }
+
+    /// Returns the point which `advance` will return next.
+    fn peek(&self) -> Vec2 {
+        if (self.pos_ew.x * self.dir.x) < (self.pos_ns.x * self.dir.x) {
+            self.pos_ew
+        } else {
+            self.pos_ns
+        }
+    }
+}
+
+impl Iterator for Hits<'_> {
+    type Item = Intersection;
+
+    fn next(&mut self) -> Option<Intersection> {
+        let map = self.map;
+        let ray = self.ray;
+
+        // The ray can start inside a door's cell.
+        if !self.started {
+            self.started = true;
+
+            let hit = self
+                .prev_idx
+                .and_then(|idx| map.intersect_door(ray, ray.pos, idx))
+                .filter(|hit| self.in_reach(hit.pos));
+
+            if hit.is_some() {
+                return hit;
+            }
+        }
+
+        while !self.done {
+            let (res, idx, in_ns_dir) = self.advance();
+
+            // The ray has gone too far or has left the map.
+            let idx = match idx {
+                Some(idx) if self.in_reach(res) => idx,
+                _ => {
+                    self.done = true;
+                    break;
                 }
-            } else {
-                let res = pos_ns;
-                pos_ns += step_ns;
-
-                let idx_x = if dir.x > 0.0 { res.x } else { res.x - 1.0 } as usize;
-                let idx_y = res.y as usize;
-
-                if let Some(tex) = self.grid[self.wd * idx_y + idx_x] {
-                    return Intersection {
-                        pos: res,
-                        tex,
-                        in_ns_dir: true,
-                    };
+            };
+
+            let prev_idx = self.prev_idx.replace(idx);
+
+            if let Some(tex) = map.grid[idx] {
+                let face = Face::hit_by(ray.dir, in_ns_dir);
+
+                // Walls on the sides of a door show its frame. The wall behind
+                // it (facing the same way as the door) doesn't.
+                let door = prev_idx.and_then(|idx| map.doors[idx].as_ref());
+
+                let tex = match door.filter(|door| door.in_ns_dir != in_ns_dir) {
+                    Some(door) => door.frame_tex,
+                    None => map.face_tex[idx].map_or(tex, |faces| faces[face as usize]),
+                };
+
+                return Some(Intersection {
+                    pos: res,
+                    tex,
+                    in_ns_dir,
+                    tex_x: if in_ns_dir {
+                        res.y.fract()
+                    } else {
+                        res.x.fract()
+                    },
+                    idx,
+                    face,
+                    ht: map.heights[idx],
+                    exit: self.peek(),
+                });
+            }
+
+            if let Some(hit) = map.intersect_door(ray, res, idx) {
+                if self.in_reach(hit.pos) {
+                    return Some(hit);
                 }
             }
         }
+
+        None
     }
 }
 
@@ -157,21 +511,34 @@ mod tests {
         '0', '0', '0', '0', '0', '0', // row-5
     ];
 
+    /// Like `GRID` but with a hole in the east border.
+    const OPEN_GRID: [char; 36] = [
+        '0', '0', '0', '0', '0', '0', // row-0
+        '0', ' ', ' ', ' ', ' ', '0', // row-1
+        '0', ' ', ' ', ' ', ' ', ' ', // row-2
+        '0', ' ', ' ', ' ', ' ', '0', // row-3
+        '0', ' ', ' ', ' ', ' ', '0', // row-4
+        '0', '0', '0', '0', '0', '0', // row-5
+    ];
+
     fn make_map() -> Map {
-        Map {
-            wd: 6,
-            ht: 6,
-            grid: GRID
-                .iter()
+        make_map_from(&GRID)
+    }
+
+    fn make_map_from(grid: &[char; 36]) -> Map {
+        Map::new(
+            6,
+            6,
+            grid.iter()
                 .map(|&c| {
                     if c == ' ' {
                         None
                     } else {
-                        Some(c.to_digit(16).unwrap() as u8)
+                        Some(c.to_digit(16).unwrap() as TexId)
                     }
                 })
                 .collect(),
-        }
+        )
     }
 
     #[test]
@@ -180,11 +547,11 @@ mod tests {
 
         let old_pos = Vec2::new(4.8, 4.8);
         let new_pos = Vec2::new(5.1, 4.9);
-        assert_eq!(map.resolve_collisions(old_pos, new_pos), Vec2::new(4.8, 4.9));
+        assert_eq!(map.resolve_collisions(old_pos, new_pos, 0.0), Vec2::new(4.8, 4.9));
 
         let old_pos = Vec2::new(1.2, 1.2);
         let new_pos = Vec2::new(0.9, 1.1);
-        assert_eq!(map.resolve_collisions(old_pos, new_pos), Vec2::new(1.2, 1.1));
+        assert_eq!(map.resolve_collisions(old_pos, new_pos, 0.0), Vec2::new(1.2, 1.1));
     }
 
     #[test]
@@ -193,18 +560,95 @@ mod tests {
         let old_pos = Vec2::new(4.8, 4.8);
         let new_pos = Vec2::new(4.9, 5.1);
         
-        assert_eq!(map.resolve_collisions(old_pos, new_pos), Vec2::new(4.9, 4.8));
+        assert_eq!(map.resolve_collisions(old_pos, new_pos, 0.0), Vec2::new(4.9, 4.8));
 
         let old_pos = Vec2::new(1.2, 1.2);
         let new_pos = Vec2::new(1.1, 0.9);
-        assert_eq!(map.resolve_collisions(old_pos, new_pos), Vec2::new(1.1, 1.2));
+        assert_eq!(map.resolve_collisions(old_pos, new_pos, 0.0), Vec2::new(1.1, 1.2));
+    }
+
+    #[test]
+    fn resolve_collisions_keeps_radius_away_from_walls() {
+        let map = make_map();
+        let old_pos = Vec2::new(4.7, 2.5);
+        let new_pos = Vec2::new(4.9, 2.6);
+
+        assert_eq!(
+            map.resolve_collisions(old_pos, new_pos, 0.25),
+            Vec2::new(4.7, 2.6)
+        );
+
+        let old_pos = Vec2::new(1.3, 2.5);
+        let new_pos = Vec2::new(1.2, 2.4);
+        assert_eq!(
+            map.resolve_collisions(old_pos, new_pos, 0.25),
+            Vec2::new(1.3, 2.4)
+        );
+    }
+
+    #[test]
+    fn resolve_collisions_given_radius_slides_along_walls() {
+        let map = make_map();
+        let old_pos = Vec2::new(2.5, 1.3);
+        let new_pos = Vec2::new(3.5, 1.1);
+
+        assert_eq!(
+            map.resolve_collisions(old_pos, new_pos, 0.25),
+            Vec2::new(3.5, 1.3)
+        );
+    }
+
+    #[test]
+    fn resolve_collisions_given_radius_handles_corners() {
+        // A pillar at (3, 2).
+        let mut map = make_map();
+        map.grid[15] = Some(1);
+
+        // Going diagonally towards the pillar's corner is blocked only along
+        // the axis which would make the circle touch it. Moving along x comes
+        // first and keeps the circle clear, so only y is blocked.
+        let old_pos = Vec2::new(2.6, 1.6);
+        let new_pos = Vec2::new(2.85, 1.85);
+        let res = map.resolve_collisions(old_pos, new_pos, 0.25);
+
+        assert!(map.overlap(res, 0.25).is_none());
+        assert!((res - Vec2::new(2.85, 1.6)).len() < 1e-5);
+    }
+
+    #[test]
+    fn resolve_collisions_given_fast_movement_doesnt_tunnel() {
+        // A wall across the middle of the map, from (3, 1) to (3, 4).
+        let mut map = make_map();
+        for y in 1..5 {
+            map.grid[6 * y + 3] = Some(1);
+        }
+
+        let old_pos = Vec2::new(1.5, 2.5);
+        let new_pos = Vec2::new(4.5, 2.5);
+        let res = map.resolve_collisions(old_pos, new_pos, 0.25);
+
+        assert!(res.x < 3.0 - 0.25);
+        assert_eq!(res.y, 2.5);
+    }
+
+    #[test]
+    fn resolve_collisions_lets_player_move_out_of_walls() {
+        let map = make_map();
+        let old_pos = Vec2::new(4.9, 2.5);
+        let new_pos = Vec2::new(4.7, 2.5);
+
+        assert_eq!(map.resolve_collisions(old_pos, new_pos, 0.25), new_pos);
+        assert_eq!(
+            map.resolve_collisions(old_pos, Vec2::new(4.95, 2.5), 0.25),
+            old_pos
+        );
     }
 
     #[test]
     fn intersect_given_horizontal_ray_works_fine() {
         let map = make_map();
         let ray = Ray2::new(Vec2::new(1.5, 1.5), Vec2::new(1.0, 0.0));
-        let intersection = map.intersect(&ray);
+        let intersection = map.intersect(&ray, 100.0).unwrap();
 
         assert_eq!(intersection.pos, Vec2::new(5.0, 1.5));
         assert_eq!(intersection.tex, 0);
@@ -215,7 +659,7 @@ mod tests {
     fn intersect_recognizes_ew_intersection() {
         let map = make_map();
         let ray = Ray2::new(Vec2::new(2.0, 2.0), Vec2::new(1.0, 2.0));
-        let intersection = map.intersect(&ray);
+        let intersection = map.intersect(&ray, 100.0).unwrap();
 
         assert!(!intersection.in_ns_dir);
     }
@@ -224,8 +668,162 @@ mod tests {
     fn intersect_recognizes_ns_intersection() {
         let map = make_map();
         let ray = Ray2::new(Vec2::new(2.0, 2.0), Vec2::new(2.0, 1.0));
-        let intersection = map.intersect(&ray);
+        let intersection = map.intersect(&ray, 100.0).unwrap();
 
         assert!(intersection.in_ns_dir);
     }
+
+    #[test]
+    fn resolve_collisions_keeps_player_inside_open_map() {
+        let map = make_map_from(&OPEN_GRID);
+        let old_pos = Vec2::new(5.8, 2.5);
+        let new_pos = Vec2::new(6.1, 2.6);
+
+        assert_eq!(
+            map.resolve_collisions(old_pos, new_pos, 0.0),
+            Vec2::new(5.8, 2.6)
+        );
+    }
+
+    #[test]
+    fn intersect_given_ray_leaving_map_returns_none() {
+        let map = make_map_from(&OPEN_GRID);
+        let ray = Ray2::new(Vec2::new(1.5, 2.5), Vec2::new(1.0, 0.0));
+
+        assert!(map.intersect(&ray, 100.0).is_none());
+    }
+
+    #[test]
+    fn intersect_given_far_wall_returns_none() {
+        let map = make_map();
+        let ray = Ray2::new(Vec2::new(1.5, 1.5), Vec2::new(1.0, 0.0));
+
+        assert!(map.intersect(&ray, 3.0).is_none());
+        assert!(map.intersect(&ray, 4.0).is_some());
+    }
+
+    #[test]
+    fn hits_continue_past_walls() {
+        let mut map = make_map();
+        map.grid[6 + 3] = Some(1);
+        map.heights[6 + 3] = 0.5;
+
+        let ray = Ray2::new(Vec2::new(1.5, 1.5), Vec2::new(1.0, 0.0));
+        let hits: Vec<_> = map.hits(&ray, 100.0).collect();
+
+        assert_eq!(hits.len(), 2);
+        assert_eq!(
+            (hits[0].pos, hits[0].exit),
+            (Vec2::new(3.0, 1.5), Vec2::new(4.0, 1.5))
+        );
+        assert_eq!((hits[0].tex, hits[0].ht), (1, 0.5));
+        assert_eq!((hits[1].pos, hits[1].ht), (Vec2::new(5.0, 1.5), 1.0));
+        assert_eq!(map.max_ht(), 1.0);
+    }
+
+    #[test]
+    fn intersect_uses_texture_of_face_hit() {
+        let mut map = make_map();
+        map.face_tex[6 + 5] = Some([1, 2, 3, 4]);
+
+        let ray = Ray2::new(Vec2::new(1.5, 1.5), Vec2::new(1.0, 0.0));
+        let intersection = map.intersect(&ray, 100.0).unwrap();
+        assert_eq!((intersection.face, intersection.tex), (Face::West, 4));
+
+        let ray = Ray2::new(Vec2::new(1.5, 2.5), Vec2::new(1.0, 0.0));
+        assert_eq!(map.intersect(&ray, 100.0).unwrap().tex, 0);
+    }
+
+    /// Index of the door's cell i.e (3, 2) in `make_map_with_door`.
+    const DOOR_IDX: usize = 15;
+
+    /// Returns `GRID` with a door at (3, 2) between walls at (3, 1) and (3, 3).
+    fn make_map_with_door() -> Map {
+        let mut map = make_map();
+        map.grid[9] = Some(1);
+        map.grid[21] = Some(1);
+        map.doors[DOOR_IDX] = Some(Door::new(2, 3, map.door_in_ns_dir(3, 2)));
+        map
+    }
+
+    #[test]
+    fn intersect_hits_closed_door_in_middle_of_cell() {
+        let map = make_map_with_door();
+        let ray = Ray2::new(Vec2::new(1.5, 2.25), Vec2::new(1.0, 0.0));
+        let intersection = map.intersect(&ray, 100.0).unwrap();
+
+        assert_eq!(intersection.pos, Vec2::new(3.5, 2.25));
+        assert_eq!(intersection.tex, 2);
+        assert_eq!(intersection.tex_x, 0.25);
+        assert!(intersection.in_ns_dir);
+    }
+
+    #[test]
+    fn intersect_passes_through_open_part_of_door() {
+        let mut map = make_map_with_door();
+        map.doors[DOOR_IDX].as_mut().unwrap().openness = 0.5;
+
+        let ray = Ray2::new(Vec2::new(1.5, 2.25), Vec2::new(1.0, 0.0));
+        let intersection = map.intersect(&ray, 100.0).unwrap();
+        assert_eq!(intersection.pos, Vec2::new(5.0, 2.25));
+
+        let ray = Ray2::new(Vec2::new(1.5, 2.75), Vec2::new(1.0, 0.0));
+        let intersection = map.intersect(&ray, 100.0).unwrap();
+        assert_eq!(intersection.pos, Vec2::new(3.5, 2.75));
+        assert_eq!(intersection.tex_x, 0.25);
+    }
+
+    #[test]
+    fn intersect_given_far_door_returns_none() {
+        let map = make_map_with_door();
+        let ray = Ray2::new(Vec2::new(1.5, 2.25), Vec2::new(1.0, 0.0));
+
+        assert!(map.intersect(&ray, 1.9).is_none());
+        assert!(map.intersect(&ray, 2.1).is_some());
+
+        // The ray starts in the door's cell.
+        let ray = Ray2::new(Vec2::new(3.1, 2.25), Vec2::new(1.0, 0.0));
+
+        assert!(map.intersect(&ray, 0.3).is_none());
+        assert!(map.intersect(&ray, 0.5).is_some());
+    }
+
+    #[test]
+    fn intersect_shows_door_frame_on_side_walls() {
+        let map = make_map_with_door();
+        let ray = Ray2::new(Vec2::new(3.2, 2.4), Vec2::new(0.3, -1.0).normalized());
+        let intersection = map.intersect(&ray, 100.0).unwrap();
+
+        assert_eq!(intersection.tex, 3);
+        assert!(!intersection.in_ns_dir);
+    }
+
+    #[test]
+    fn intersect_shows_own_texture_on_wall_behind_door() {
+        let mut map = make_map_with_door();
+        map.grid[16] = Some(4);
+        map.doors[DOOR_IDX].as_mut().unwrap().openness = 1.0;
+
+        let ray = Ray2::new(Vec2::new(1.5, 2.5), Vec2::new(1.0, 0.0));
+        let intersection = map.intersect(&ray, 100.0).unwrap();
+
+        assert_eq!(intersection.pos, Vec2::new(4.0, 2.5));
+        assert_eq!(intersection.tex, 4);
+    }
+
+    #[test]
+    fn resolve_collisions_blocks_closed_door_only() {
+        let mut map = make_map_with_door();
+        let old_pos = Vec2::new(2.9, 2.5);
+        let new_pos = Vec2::new(3.1, 2.5);
+
+        assert_eq!(map.resolve_collisions(old_pos, new_pos, 0.0), old_pos);
+
+        map.use_door(old_pos, Vec2::new(1.0, 0.0));
+        map.update(0.5);
+        assert_eq!(map.resolve_collisions(old_pos, new_pos, 0.0), old_pos);
+
+        map.update(0.5);
+        assert_eq!(map.resolve_collisions(old_pos, new_pos, 0.0), new_pos);
+    }
 }
@@ -4,10 +4,12 @@ use std::io::{self, prelude::*};
 use std::path::Path;
 use std::str::{Split, SplitTerminator};
 
-use super::Map;
+use super::reader_v2::read_map_v2;
+use super::{Map, TexId};
 use MapReadError::*;
 
 use thiserror::Error;
+use toml::de;
 
 #[derive(Debug, Error)]
 pub enum MapReadError {
@@ -17,11 +19,31 @@ pub enum MapReadError {
     ParsingError { line_no: usize },
     #[error("Missing grid entry in line {line_no:?}")]
     MissingEntry { line_no: usize },
+    #[error("Format error")]
+    TomlError(#[from] de::Error),
+    #[error("Unsupported map format version {0}")]
+    UnsupportedVersion(u32),
+    #[error("Legend key {token:?} isn't {token_wd} character(s) long")]
+    BadLegendKey { token: String, token_wd: usize },
+    #[error("Unknown texture {name:?} in legend")]
+    UnknownTexture { name: String },
+    #[error("Row {y} has a different width than the first row")]
+    RaggedRow { y: usize },
+    #[error("Cell ({x}, {y}): token {token:?} isn't in the legend")]
+    UnknownToken { x: usize, y: usize, token: String },
+    #[error("The {layer} layer has different dimensions than the grid")]
+    LayerSizeMismatch { layer: &'static str },
+    #[error("Legend entry {token:?} has height {ht}. Heights must be positive")]
+    BadHeight { token: String, ht: f32 },
 }
 
 /// Attempts to read a `Map` from `path`.
 ///
-/// All map files start with a one line header containing space separated width
+/// Files with a `.toml` extension are read as v2 maps (see `read_map_v2`).
+/// `tex_names` is used to resolve the texture names used in them. All other
+/// files are read as v1 maps which are described below.
+///
+/// All v1 map files start with a one line header containing space separated width
 /// and height of the map. This is followed by a height x width grid specifying
 /// textures of each cell of the map.
 ///
@@ -31,16 +53,25 @@ pub enum MapReadError {
 /// that the cell is empty and a hex-digit means the cell holds a wall with the
 /// texture id equal to the value of hex-digit.
 ///
-/// Use of hex-digits means that we can only have a maximum of 16 textures. IMO
-/// this is not a problem.
-pub fn read_map<P: AsRef<Path> + fmt::Debug>(path: P) -> Result<Map, MapReadError> {
+/// Use of hex-digits means that we can only have a maximum of 16 textures. v2
+/// maps don't have this limit.
+pub fn read_map<P: AsRef<Path> + fmt::Debug>(
+    path: P,
+    tex_names: &[String],
+) -> Result<Map, MapReadError> {
     info!("Loading map at {:?}", path);
 
+    let is_v2 = path.as_ref().extension().is_some_and(|ext| ext == "toml");
+
     let mut file = File::open(path)?;
     let mut contents = String::new();
 
     file.read_to_string(&mut contents)?;
 
+    if is_v2 {
+        return read_map_v2(&contents, tex_names);
+    }
+
     let mut lines = contents.split_terminator('\n');
     let header = lines.next().ok_or(ParsingError { line_no: 1 })?.split(' ');
 
@@ -50,7 +81,7 @@ pub fn read_map<P: AsRef<Path> + fmt::Debug>(path: P) -> Result<Map, MapReadErro
 
     let grid = read_grid(wd, ht, lines)?;
 
-    Ok(Map { wd, ht, grid })
+    Ok(Map::new(wd, ht, grid))
 }
 
 fn read_dims(mut header: Split<char>) -> Result<(usize, usize), MapReadError> {
@@ -79,7 +110,7 @@ fn read_grid(
     wd: usize,
     ht: usize,
     mut lines: SplitTerminator<char>,
-) -> Result<Vec<Option<u8>>, MapReadError> {
+) -> Result<Vec<Option<TexId>>, MapReadError> {
     let mut grid = Vec::with_capacity(wd * ht);
 
     for y in 0..ht {
@@ -92,7 +123,7 @@ fn read_grid(
                 grid.push(None);
             } else {
                 let d = c.to_digit(16).ok_or(ParsingError { line_no })?;
-                grid.push(Some(d as u8));
+                grid.push(Some(d as TexId));
             }
         }
     }
@@ -0,0 +1,599 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Deserialize;
+
+use super::reader::MapReadError::{self, *};
+use super::{Door, Light, Map, TexId};
+
+/// Ambient light of maps which have lights but don't specify it.
+const DEFAULT_AMBIENT: [u8; 3] = [64, 64, 64];
+
+/// The layout of a v2 map file.
+#[derive(Deserialize)]
+struct MapFile {
+    version: u32,
+    #[serde(default = "default_token_wd")]
+    token_wd: usize,
+    grid: Vec<String>,
+    floor: Option<Vec<String>>,
+    ceiling: Option<Vec<String>>,
+    legend: BTreeMap<String, LegendEntry>,
+    #[serde(default)]
+    lights: Vec<Light>,
+    ambient: Option<[u8; 3]>,
+    outdoors: Option<Outdoors>,
+}
+
+fn default_token_wd() -> usize {
+    1
+}
+
+/// The value of an entry in a v2 map's legend. Misspelled keys in tables are
+/// errors rather than being ignored.
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum LegendEntry {
+    /// A wall with the named texture.
+    Wall(String),
+    /// A door. `frame` defaults to the door's own texture.
+    Door { door: String, frame: Option<String> },
+    /// A wall with the named texture. It can be `ht` units tall instead of 1
+    /// and its faces can have textures of their own.
+    Block {
+        wall: String,
+        ht: Option<f32>,
+        north: Option<String>,
+        east: Option<String>,
+        south: Option<String>,
+        west: Option<String>,
+    },
+}
+
+/// Which cells of a v2 map are open to the sky.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Outdoors {
+    /// Either every cell or none.
+    All(bool),
+    /// A grid like `grid`. Tokens which aren't only spaces are outdoors.
+    Cells(Vec<String>),
+}
+
+/// What a token resolves to after looking up its texture names.
+#[derive(Clone, Copy)]
+enum Tile {
+    Wall {
+        tex: TexId,
+        ht: f32,
+        faces: Option<[TexId; 4]>,
+    },
+    Door {
+        tex: TexId,
+        frame_tex: TexId,
+    },
+}
+
+impl Tile {
+    fn tex(self) -> TexId {
+        match self {
+            Tile::Wall { tex, .. } => tex,
+            Tile::Door { tex, .. } => tex,
+        }
+    }
+}
+
+/// Attempts to read a v2 `Map` from `contents`.
+///
+/// v2 maps are TOML files. They look like this:
+///
+/// ```toml
+/// version = 2
+/// grid = [
+///     "#####",
+///     "#   E",
+///     "#####",
+/// ]
+///
+/// [legend]
+/// "#" = "red_brick"
+/// "E" = "eagle"
+/// ```
+///
+/// Each row of `grid` is split into tokens of `token_wd` characters (1 by
+/// default). A token made only of spaces is an empty cell. Any other token is
+/// a wall whose texture is given by `legend`. Texture names are looked up in
+/// `tex_names` and the texture id is the index of the name in it.
+///
+/// Unlike v1 maps, there is no header. The map's height is the number of rows
+/// and its width is the number of tokens in each row.
+///
+/// Legend entries can also be doors like `"D" = { door = "wood", frame =
+/// "grey_stone" }`. `frame` is the texture of the walls on the door's sides
+/// and is optional.
+///
+/// The optional `floor` and `ceiling` fields specify the textures of the floor
+/// and ceiling of each cell. They're grids just like `grid` and use the same
+/// legend. Their empty cells use the default floor texture.
+///
+/// Maps can have point lights like:
+///
+/// ```toml
+/// ambient = [32, 32, 48]
+///
+/// [[lights]]
+/// pos = { x = 2.5, y = 1.5 }
+/// color = [255, 200, 120]
+/// radius = 6.0
+/// ```
+///
+/// If a map has lights or `ambient`, its lightmap is baked. Otherwise, it is
+/// fully lit.
+///
+/// Walls are 1 unit tall unless their legend entry gives a height like `"T" =
+/// { wall = "grey_stone", ht = 2.5 }`. Walls of other heights are best used
+/// outdoors as taller walls poke through the ceiling.
+///
+/// Such entries can also give textures to some of the wall's faces like `"B" =
+/// { wall = "red_brick", south = "wood" }`. Other faces use `wall`.
+///
+/// `outdoors = true` makes every cell open to the sky. It can also be a grid
+/// like `grid` in which tokens other than spaces are outdoors. Its tokens don't
+/// need to be in the legend.
+pub fn read_map_v2(contents: &str, tex_names: &[String]) -> Result<Map, MapReadError> {
+    let file: MapFile = toml::from_str(contents)?;
+
+    if file.version != 2 {
+        return Err(UnsupportedVersion(file.version));
+    }
+
+    let token_wd = file.token_wd.max(1);
+    let legend = read_legend(&file.legend, token_wd, tex_names)?;
+
+    let ht = file.grid.len();
+    let (wd, tiles) = read_layer(&file.grid, token_wd, &legend)?;
+
+    debug!("Map is {} by {}", ht, wd);
+
+    let grid = tiles
+        .iter()
+        .map(|tile| match tile {
+            Some(Tile::Wall { tex, .. }) => Some(*tex),
+            _ => None,
+        })
+        .collect();
+
+    let mut map = Map::new(wd, ht, grid);
+
+    for (idx, tile) in tiles.iter().enumerate() {
+        match *tile {
+            Some(Tile::Wall { ht, faces, .. }) => {
+                map.heights[idx] = ht;
+                map.face_tex[idx] = faces;
+            }
+            Some(Tile::Door { tex, frame_tex }) => {
+                let in_ns_dir = map.door_in_ns_dir(idx % wd, idx / wd);
+                map.doors[idx] = Some(Door::new(tex, frame_tex, in_ns_dir));
+            }
+            None => {}
+        }
+    }
+
+    if let Some(floor) = &file.floor {
+        map.floor = read_extra_layer(floor, "floor", &map, token_wd, &legend)?;
+    }
+
+    if let Some(ceiling) = &file.ceiling {
+        map.ceil = read_extra_layer(ceiling, "ceiling", &map, token_wd, &legend)?;
+    }
+
+    match &file.outdoors {
+        Some(Outdoors::All(all)) => map.outdoors = vec![*all; wd * ht],
+        Some(Outdoors::Cells(rows)) => map.outdoors = read_outdoors(rows, &map, token_wd)?,
+        None => {}
+    }
+
+    if !file.lights.is_empty() || file.ambient.is_some() {
+        map.lights = file.lights;
+        map.ambient = file.ambient.unwrap_or(DEFAULT_AMBIENT);
+        map.bake_lightmap();
+    }
+
+    Ok(map)
+}
+
+/// Reads a layer like `floor` which must have the same dimensions as `map`.
+fn read_extra_layer(
+    rows: &[String],
+    layer: &'static str,
+    map: &Map,
+    token_wd: usize,
+    legend: &HashMap<String, Tile>,
+) -> Result<Vec<Option<TexId>>, MapReadError> {
+    let (wd, tiles) = read_layer(rows, token_wd, legend)?;
+
+    if wd != map.wd || rows.len() != map.ht {
+        return Err(LayerSizeMismatch { layer });
+    }
+
+    Ok(tiles.into_iter().map(|tile| tile.map(Tile::tex)).collect())
+}
+
+/// Reads the `outdoors` grid. It must have the same dimensions as `map`. Like
+/// other layers, its cells are tokens of `token_wd` characters.
+fn read_outdoors(rows: &[String], map: &Map, token_wd: usize) -> Result<Vec<bool>, MapReadError> {
+    let mut res = Vec::with_capacity(map.wd * map.ht);
+
+    for row in rows {
+        let chars: Vec<_> = row.chars().collect();
+
+        if chars.len() != map.wd * token_wd {
+            return Err(LayerSizeMismatch { layer: "outdoors" });
+        }
+
+        let tokens = chars.chunks(token_wd);
+        res.extend(tokens.map(|token| token.iter().any(|&c| c != ' ')));
+    }
+
+    if rows.len() != map.ht {
+        return Err(LayerSizeMismatch { layer: "outdoors" });
+    }
+
+    Ok(res)
+}
+
+/// Reads a grid of tokens. Returns its width along with its cells.
+fn read_layer(
+    rows: &[String],
+    token_wd: usize,
+    legend: &HashMap<String, Tile>,
+) -> Result<(usize, Vec<Option<Tile>>), MapReadError> {
+    let mut wd = None;
+    let mut grid = Vec::new();
+
+    for (y, row) in rows.iter().enumerate() {
+        let chars: Vec<_> = row.chars().collect();
+
+        let row_wd = chars.len() / token_wd;
+
+        if chars.len() % token_wd != 0 || row_wd != *wd.get_or_insert(row_wd) {
+            return Err(RaggedRow { y });
+        }
+
+        for (x, token) in chars.chunks(token_wd).enumerate() {
+            let token: String = token.iter().collect();
+
+            if token.trim().is_empty() {
+                grid.push(None);
+            } else {
+                let tile = legend.get(&token).ok_or(UnknownToken { x, y, token })?;
+                grid.push(Some(*tile));
+            }
+        }
+    }
+
+    Ok((wd.unwrap_or(0), grid))
+}
+
+fn read_legend(
+    legend: &BTreeMap<String, LegendEntry>,
+    token_wd: usize,
+    tex_names: &[String],
+) -> Result<HashMap<String, Tile>, MapReadError> {
+    let tex_id = |name: &String| {
+        tex_names
+            .iter()
+            .position(|tex_name| tex_name == name)
+            .map(|tex| tex as TexId)
+            .ok_or_else(|| UnknownTexture { name: name.clone() })
+    };
+
+    let mut res = HashMap::with_capacity(legend.len());
+
+    for (token, entry) in legend {
+        if token.chars().count() != token_wd {
+            return Err(BadLegendKey {
+                token: token.clone(),
+                token_wd,
+            });
+        }
+
+        let tile = match entry {
+            LegendEntry::Wall(name) => Tile::Wall {
+                tex: tex_id(name)?,
+                ht: 1.0,
+                faces: None,
+            },
+            LegendEntry::Door { door, frame } => Tile::Door {
+                tex: tex_id(door)?,
+                frame_tex: tex_id(frame.as_ref().unwrap_or(door))?,
+            },
+            LegendEntry::Block {
+                wall,
+                ht,
+                north,
+                east,
+                south,
+                west,
+            } => {
+                let ht = ht.unwrap_or(1.0);
+
+                if !(ht > 0.0 && ht.is_finite()) {
+                    return Err(BadHeight {
+                        token: token.clone(),
+                        ht,
+                    });
+                }
+
+                let tex = tex_id(wall)?;
+                let face_tex = |name: &Option<String>| name.as_ref().map_or(Ok(tex), tex_id);
+
+                let faces = if [north, east, south, west].iter().any(|name| name.is_some()) {
+                    Some([
+                        face_tex(north)?,
+                        face_tex(east)?,
+                        face_tex(south)?,
+                        face_tex(west)?,
+                    ])
+                } else {
+                    None
+                };
+
+                Tile::Wall { tex, ht, faces }
+            }
+        };
+
+        res.insert(token.clone(), tile);
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tex_names() -> Vec<String> {
+        vec!["eagle".to_string(), "red_brick".to_string()]
+    }
+
+    #[test]
+    fn read_map_v2_resolves_legend() {
+        let contents = r#"
+            version = 2
+            grid = [
+                "RRR",
+                "R E",
+                "RRR",
+            ]
+
+            [legend]
+            "R" = "red_brick"
+            "E" = "eagle"
+        "#;
+        let map = read_map_v2(contents, &tex_names()).unwrap();
+
+        assert_eq!((map.wd, map.ht), (3, 3));
+        assert_eq!(map.grid[3..6], [Some(1), None, Some(0)]);
+    }
+
+    #[test]
+    fn read_map_v2_given_multi_char_tokens() {
+        let contents = r#"
+            version = 2
+            token_wd = 2
+            grid = [
+                "rbrbrb",
+                "rb  e1",
+            ]
+
+            [legend]
+            "rb" = "red_brick"
+            "e1" = "eagle"
+        "#;
+        let map = read_map_v2(contents, &tex_names()).unwrap();
+
+        assert_eq!((map.wd, map.ht), (3, 2));
+        assert_eq!(map.grid[3..6], [Some(1), None, Some(0)]);
+    }
+
+    #[test]
+    fn read_map_v2_reports_unknown_token() {
+        let contents = r#"
+            version = 2
+            grid = ["R?"]
+
+            [legend]
+            "R" = "red_brick"
+        "#;
+        let res = read_map_v2(contents, &tex_names());
+
+        assert!(matches!(res, Err(UnknownToken { x: 1, y: 0, .. })));
+    }
+
+    #[test]
+    fn read_map_v2_reports_unknown_texture() {
+        let contents = r#"
+            version = 2
+            grid = ["R"]
+
+            [legend]
+            "R" = "marble"
+        "#;
+        let res = read_map_v2(contents, &tex_names());
+
+        assert!(matches!(res, Err(UnknownTexture { .. })));
+    }
+
+    #[test]
+    fn read_map_v2_reads_floor_and_ceiling() {
+        let contents = r#"
+            version = 2
+            grid = ["R R"]
+            floor = [" E "]
+            ceiling = ["RRE"]
+
+            [legend]
+            "R" = "red_brick"
+            "E" = "eagle"
+        "#;
+        let map = read_map_v2(contents, &tex_names()).unwrap();
+
+        assert_eq!(map.floor, [None, Some(0), None]);
+        assert_eq!(map.ceil, [Some(1), Some(1), Some(0)]);
+    }
+
+    #[test]
+    fn read_map_v2_reports_layer_size_mismatch() {
+        let contents = r#"
+            version = 2
+            grid = ["R R"]
+            floor = ["EE"]
+
+            [legend]
+            "R" = "red_brick"
+            "E" = "eagle"
+        "#;
+        let res = read_map_v2(contents, &tex_names());
+
+        assert!(matches!(res, Err(LayerSizeMismatch { layer: "floor" })));
+    }
+
+    #[test]
+    fn read_map_v2_reads_lights() {
+        let contents = r#"
+            version = 2
+            grid = ["R R"]
+
+            [[lights]]
+            pos = { x = 1.5, y = 0.5 }
+            color = [255, 128, 0]
+            radius = 2.0
+
+            [legend]
+            "R" = "red_brick"
+        "#;
+        let map = read_map_v2(contents, &tex_names()).unwrap();
+
+        assert_eq!(map.lights.len(), 1);
+        assert_eq!(map.ambient, DEFAULT_AMBIENT);
+        assert!(map.lightmap.is_some());
+    }
+
+    #[test]
+    fn read_map_v2_reads_outdoors() {
+        let contents = r#"
+            version = 2
+            grid = ["R  R"]
+            outdoors = [" oo "]
+
+            [legend]
+            "R" = "red_brick"
+        "#;
+        let map = read_map_v2(contents, &tex_names()).unwrap();
+        assert_eq!(map.outdoors, [false, true, true, false]);
+
+        let contents = contents.replace(r#"[" oo "]"#, "true");
+        let map = read_map_v2(&contents, &tex_names()).unwrap();
+        assert_eq!(map.outdoors, [true; 4]);
+    }
+
+    #[test]
+    fn read_map_v2_reads_outdoors_by_token() {
+        let contents = r#"
+            version = 2
+            token_wd = 2
+            grid = ["RR    RR"]
+            outdoors = ["   o o  "]
+
+            [legend]
+            "RR" = "red_brick"
+        "#;
+        let map = read_map_v2(contents, &tex_names()).unwrap();
+
+        assert_eq!(map.outdoors, [false, true, true, false]);
+    }
+
+    #[test]
+    fn read_map_v2_reads_doors() {
+        let contents = r#"
+            version = 2
+            grid = [
+                "RRR",
+                "RDR",
+                "R R",
+            ]
+
+            [legend]
+            "R" = "red_brick"
+            "D" = { door = "eagle", frame = "red_brick" }
+        "#;
+        let map = read_map_v2(contents, &tex_names()).unwrap();
+        let door = map.doors[4].as_ref().unwrap();
+
+        assert_eq!(map.grid[4], None);
+        assert_eq!((door.tex, door.frame_tex), (0, 1));
+        assert!(!door.in_ns_dir);
+    }
+
+    #[test]
+    fn read_map_v2_reads_wall_heights() {
+        let contents = r#"
+            version = 2
+            grid = ["RLT"]
+
+            [legend]
+            "R" = "red_brick"
+            "L" = { wall = "eagle", ht = 0.5 }
+            "T" = { wall = "red_brick", ht = 3.0 }
+        "#;
+        let map = read_map_v2(contents, &tex_names()).unwrap();
+
+        assert_eq!(map.grid, [Some(1), Some(0), Some(1)]);
+        assert_eq!(map.heights, [1.0, 0.5, 3.0]);
+    }
+
+    #[test]
+    fn read_map_v2_rejects_bad_heights() {
+        let contents = r#"
+            version = 2
+            grid = ["L"]
+
+            [legend]
+            "L" = { wall = "eagle", ht = 0.0 }
+        "#;
+        let res = read_map_v2(contents, &tex_names());
+
+        assert!(matches!(res, Err(BadHeight { .. })));
+    }
+
+    #[test]
+    fn read_map_v2_reads_face_textures() {
+        let contents = r#"
+            version = 2
+            grid = ["BR"]
+
+            [legend]
+            "R" = "red_brick"
+            "B" = { wall = "red_brick", east = "eagle" }
+        "#;
+        let map = read_map_v2(contents, &tex_names()).unwrap();
+
+        assert_eq!(map.grid, [Some(1), Some(1)]);
+        assert_eq!(map.face_tex, [Some([1, 0, 1, 1]), None]);
+        assert_eq!(map.heights, [1.0, 1.0]);
+    }
+
+    #[test]
+    fn read_map_v2_rejects_unknown_legend_keys() {
+        let contents = r#"
+            version = 2
+            grid = ["B"]
+
+            [legend]
+            "B" = { wall = "red_brick", nort = "eagle" }
+        "#;
+        let res = read_map_v2(contents, &tex_names());
+
+        assert!(matches!(res, Err(TomlError(_))));
+    }
+}
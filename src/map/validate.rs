@@ -0,0 +1,391 @@
+use std::error::Error;
+use std::fmt;
+
+use super::{Face, Map, TexId};
+use crate::primitive::Vec2;
+
+/// A problem found in a `Map` by `Map::validate`.
+#[derive(Debug, PartialEq)]
+pub enum MapIssue {
+    /// A cell on the map's border is empty. Rays can escape the map through it.
+    OpenBorder { x: usize, y: usize },
+    /// The player's initial position lies outside the map.
+    SpawnOutsideMap { pos: Vec2 },
+    /// The player's initial position lies inside a wall.
+    SpawnInWall { x: usize, y: usize },
+    /// A cell uses a texture id for which no texture exists.
+    UnknownTexture {
+        x: usize,
+        y: usize,
+        layer: Layer,
+        tex: TexId,
+    },
+    /// A texture id given outside the map (e.g. by the config) for which no
+    /// texture exists.
+    UnknownExtraTexture { user: TexUser, tex: usize },
+}
+
+/// The part of a cell which uses a texture.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layer {
+    Wall,
+    Floor,
+    Ceiling,
+    Door,
+    DoorFrame,
+    /// A face of a wall with its own texture.
+    Face(Face),
+}
+
+/// Something drawn along with a map whose texture isn't given by the map.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TexUser {
+    /// The floor and ceiling of cells which don't have their own.
+    DefaultFloor,
+    Sky,
+    /// The sprite at the given index.
+    Sprite(usize),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Severity {
+    /// The map works but probably not as intended.
+    Warning,
+    /// The map will crash the game or leave the player stuck.
+    Error,
+}
+
+/// Returned when a `Map` has issues of `Severity::Error`.
+#[derive(Debug)]
+pub struct InvalidMapError {
+    pub issues: Vec<MapIssue>,
+}
+
+impl fmt::Display for InvalidMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Map has {} problem(s)", self.issues.len())?;
+
+        for issue in &self.issues {
+            write!(f, "\n{}", issue)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for InvalidMapError {}
+
+impl MapIssue {
+    pub fn severity(&self) -> Severity {
+        match self {
+            MapIssue::OpenBorder { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Layer::Wall => write!(f, "wall"),
+            Layer::Floor => write!(f, "floor"),
+            Layer::Ceiling => write!(f, "ceiling"),
+            Layer::Door => write!(f, "door"),
+            Layer::DoorFrame => write!(f, "door frame"),
+            Layer::Face(Face::North) => write!(f, "north face"),
+            Layer::Face(Face::East) => write!(f, "east face"),
+            Layer::Face(Face::South) => write!(f, "south face"),
+            Layer::Face(Face::West) => write!(f, "west face"),
+        }
+    }
+}
+
+impl fmt::Display for TexUser {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TexUser::DefaultFloor => write!(f, "default floor"),
+            TexUser::Sky => write!(f, "sky"),
+            TexUser::Sprite(i) => write!(f, "sprite {}", i),
+        }
+    }
+}
+
+impl fmt::Display for MapIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapIssue::OpenBorder { x, y } => write!(f, "Cell ({}, {}): border is open", x, y),
+            MapIssue::SpawnOutsideMap { pos } => {
+                write!(
+                    f,
+                    "Spawn position ({}, {}) is outside the map",
+                    pos.x, pos.y
+                )
+            }
+            MapIssue::SpawnInWall { x, y } => {
+                write!(f, "Cell ({}, {}): spawn position is inside a wall", x, y)
+            }
+            MapIssue::UnknownTexture { x, y, layer, tex } => write!(
+                f,
+                "Cell ({}, {}): {} texture id {} doesn't exist",
+                x, y, layer, tex
+            ),
+            MapIssue::UnknownExtraTexture { user, tex } => {
+                write!(f, "Config: {} texture id {} doesn't exist", user, tex)
+            }
+        }
+    }
+}
+
+impl Map {
+    /// Checks for problems which `read_map` can't catch as they aren't syntax
+    /// errors. Returns every problem found, ordered by cell (row major).
+    ///
+    /// `tex_count` is the number of textures available and `spawn` is the
+    /// player's initial position. `extra_tex` holds the texture ids drawn
+    /// along with the map but given elsewhere. Those are checked after the
+    /// cells.
+    ///
+    /// Closed doors block the player like walls. So spawning in one is a
+    /// problem too.
+    pub fn validate(
+        &self,
+        tex_count: usize,
+        spawn: Vec2,
+        extra_tex: &[(TexUser, usize)],
+    ) -> Vec<MapIssue> {
+        let mut issues = Vec::new();
+
+        for y in 0..self.ht {
+            for x in 0..self.wd {
+                let idx = self.wd * y + x;
+                let on_border = x == 0 || y == 0 || x == self.wd - 1 || y == self.ht - 1;
+
+                if self.grid[idx].is_none() && on_border {
+                    issues.push(MapIssue::OpenBorder { x, y });
+                }
+
+                let door = self.doors[idx].as_ref();
+                let faces = self.face_tex[idx];
+                let face =
+                    |face: Face| (Layer::Face(face), faces.map(|faces| faces[face as usize]));
+                let layers = [
+                    (Layer::Wall, self.grid[idx]),
+                    (Layer::Floor, self.floor[idx]),
+                    (Layer::Ceiling, self.ceil[idx]),
+                    (Layer::Door, door.map(|door| door.tex)),
+                    (Layer::DoorFrame, door.map(|door| door.frame_tex)),
+                    face(Face::North),
+                    face(Face::East),
+                    face(Face::South),
+                    face(Face::West),
+                ];
+
+                for (layer, tex) in layers.iter() {
+                    match *tex {
+                        Some(tex) if tex as usize >= tex_count => {
+                            issues.push(MapIssue::UnknownTexture {
+                                x,
+                                y,
+                                layer: *layer,
+                                tex,
+                            })
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        for &(user, tex) in extra_tex {
+            if tex >= tex_count {
+                issues.push(MapIssue::UnknownExtraTexture { user, tex });
+            }
+        }
+
+        let is_solid = |idx: usize| {
+            self.grid[idx].is_some() || self.doors[idx].as_ref().is_some_and(|door| !door.is_open())
+        };
+
+        match self.cell_idx(spawn.x, spawn.y) {
+            None => issues.push(MapIssue::SpawnOutsideMap { pos: spawn }),
+            Some(idx) if is_solid(idx) => issues.push(MapIssue::SpawnInWall {
+                x: idx % self.wd,
+                y: idx / self.wd,
+            }),
+            Some(_) => {}
+        }
+
+        issues
+    }
+
+    /// Like `validate` but fails if any of the issues is an error. Warnings
+    /// are logged.
+    pub fn check(
+        &self,
+        tex_count: usize,
+        spawn: Vec2,
+        extra_tex: &[(TexUser, usize)],
+    ) -> Result<(), InvalidMapError> {
+        let (warnings, errors): (Vec<_>, Vec<_>) = self
+            .validate(tex_count, spawn, extra_tex)
+            .into_iter()
+            .partition(|issue| issue.severity() == Severity::Warning);
+
+        for warning in warnings {
+            warn!("{}", warning);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidMapError { issues: errors })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Door;
+
+    const GRID: [char; 16] = [
+        '0', '0', '0', '0', // row-0
+        '0', ' ', '4', '0', // row-1
+        ' ', ' ', ' ', '0', // row-2
+        '0', '0', '0', '0', // row-3
+    ];
+
+    fn make_map() -> Map {
+        Map::new(
+            4,
+            4,
+            GRID.iter()
+                .map(|&c| c.to_digit(16).map(|d| d as TexId))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn validate_reports_every_issue_with_its_cell() {
+        let map = make_map();
+        let issues = map.validate(4, Vec2::new(2.5, 1.5), &[]);
+
+        assert_eq!(
+            issues,
+            vec![
+                MapIssue::UnknownTexture {
+                    x: 2,
+                    y: 1,
+                    layer: Layer::Wall,
+                    tex: 4
+                },
+                MapIssue::OpenBorder { x: 0, y: 2 },
+                MapIssue::SpawnInWall { x: 2, y: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_given_spawn_outside_map() {
+        let map = make_map();
+        let issues = map.validate(5, Vec2::new(-1.0, 1.5), &[]);
+
+        assert_eq!(
+            issues,
+            vec![
+                MapIssue::OpenBorder { x: 0, y: 2 },
+                MapIssue::SpawnOutsideMap {
+                    pos: Vec2::new(-1.0, 1.5)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_checks_floor_and_ceiling_textures() {
+        let mut map = make_map();
+        map.grid[6] = None;
+        map.floor[5] = Some(7);
+        map.ceil[5] = Some(2);
+
+        let issues = map.validate(4, Vec2::new(1.5, 1.5), &[]);
+
+        assert_eq!(
+            issues,
+            vec![
+                MapIssue::UnknownTexture {
+                    x: 1,
+                    y: 1,
+                    layer: Layer::Floor,
+                    tex: 7
+                },
+                MapIssue::OpenBorder { x: 0, y: 2 },
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_checks_face_textures() {
+        let mut map = make_map();
+        map.face_tex[0] = Some([0, 9, 0, 0]);
+
+        let issues = map.validate(4, Vec2::new(1.5, 1.5), &[]);
+
+        assert!(issues.contains(&MapIssue::UnknownTexture {
+            x: 0,
+            y: 0,
+            layer: Layer::Face(Face::East),
+            tex: 9
+        }));
+        assert_eq!(
+            issues[0].to_string(),
+            "Cell (0, 0): east face texture id 9 doesn't exist"
+        );
+    }
+
+    #[test]
+    fn validate_checks_extra_textures() {
+        let mut map = make_map();
+        map.grid[6] = None;
+        let extra_tex = [
+            (TexUser::DefaultFloor, 3),
+            (TexUser::Sky, 4),
+            (TexUser::Sprite(1), 9),
+        ];
+
+        let issues = map.validate(4, Vec2::new(1.5, 1.5), &extra_tex);
+
+        assert_eq!(
+            issues,
+            vec![
+                MapIssue::OpenBorder { x: 0, y: 2 },
+                MapIssue::UnknownExtraTexture {
+                    user: TexUser::Sky,
+                    tex: 4
+                },
+                MapIssue::UnknownExtraTexture {
+                    user: TexUser::Sprite(1),
+                    tex: 9
+                },
+            ]
+        );
+        assert_eq!(
+            issues[2].to_string(),
+            "Config: sprite 1 texture id 9 doesn't exist"
+        );
+    }
+
+    #[test]
+    fn validate_given_spawn_in_closed_door() {
+        let mut map = make_map();
+        map.doors[5] = Some(Door::new(0, 0, true));
+
+        let issues = map.validate(4, Vec2::new(1.5, 1.5), &[]);
+        assert!(issues.contains(&MapIssue::SpawnInWall { x: 1, y: 1 }));
+
+        map.doors[5].as_mut().unwrap().openness = 1.0;
+
+        let issues = map.validate(4, Vec2::new(1.5, 1.5), &[]);
+        assert!(!issues.contains(&MapIssue::SpawnInWall { x: 1, y: 1 }));
+    }
+}
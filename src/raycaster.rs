@@ -1,25 +1,177 @@
-use crate::texture::Texture;
-use crate::{camera::Camera, map::Map};
+use std::cmp::Ordering;
+use std::f32::consts::PI;
+use std::ops::Range;
+use std::sync::{Arc, Barrier, Mutex, RwLock};
+use std::thread;
+
+use crate::camera::Camera;
+use crate::fog::Fog;
+use crate::map::{LightVal, Map, FULL_LIGHT};
+use crate::primitive::Vec2;
+use crate::sprite::Sprite;
+use crate::texture::{Sampling, Texture, TextureRegistry};
 
 pub struct Raycaster {
     pub scr_wd: u32,
     pub scr_ht: u32,
-    pub textures: Vec<Texture>,
-    /// Index of the `Texture` to be used for floor and roof.
+    pub textures: Arc<TextureRegistry>,
+    /// Index of the `Texture` to be used for floor and roof of cells which
+    /// don't specify their own.
     pub floor_id: usize,
     pub wall_ht_scale: f32,
+    /// Walls farther than this aren't drawn.
+    pub max_dist: f32,
+    /// Number of threads used for rendering. 1 means that everything is done
+    /// on the calling thread.
+    pub threads: usize,
+    /// Makes far away things fade into a color. No fog if `None`.
+    pub fog: Option<Fog>,
+    /// Index of the panoramic `Texture` shown above outdoor cells and where
+    /// rays leave the map. Those show the ceiling like other cells if `None`.
+    pub sky_id: Option<usize>,
+    /// Sample walls and floors from the mip level which suits their distance.
+    /// See `Texture::mip`.
+    pub mipmaps: bool,
+    /// How walls and floors are sampled from their textures.
+    pub sampling: Sampling,
+    /// Buffers reused by `render` from frame to frame.
+    pub scratch: Scratch,
+}
+
+/// Buffers which `Raycaster::render` keeps between frames so that it doesn't
+/// have to allocate them every time. Start with `Scratch::default()`.
+#[derive(Default)]
+pub struct Scratch {
+    /// The columns of the screen split into the chunks cast by each thread.
+    columns: Mutex<Vec<RwLock<Vec<Column>>>>,
+}
+
+/// The walls hit by the ray of a column of the screen.
+struct Column {
+    /// Direction of the ray. Going along it by `d` gets `d` farther from the
+    /// camera plane.
+    ray_dir: Vec2,
+    /// From the nearest to the farthest.
+    hits: Vec<Hit>,
+}
+
+/// Info about a wall hit by the ray of a column of the screen.
+struct Hit {
+    perp_dist: f32,
+    /// Perpendicular distance of where the ray leaves the wall's cell.
+    exit_dist: f32,
+    /// Height of the wall after scaling by `wall_ht_scale`.
+    ht: f32,
+    /// Screen rows of the top and the bottom of the wall's face. They are
+    /// fractional.
+    top: f32,
+    bot: f32,
+    /// Screen row of the far edge of the wall's top. The top is seen between
+    /// it and `top` if the wall is lower than the camera. Otherwise it is the
+    /// same as `top`.
+    cap_top: f32,
+    tex: usize,
+    /// Where the ray hit the wall as a fraction of the texture's width.
+    tex_x: f32,
+    /// How much of the texture's width one screen column covers.
+    tex_du: f32,
+    darkness: u8,
+    light: LightVal,
+}
+
+impl Hit {
+    /// Returns the rows drawn by `render_walls` for the wall's face.
+    fn face_rows(&self, scr_ht: u32) -> Range<u32> {
+        first_row(self.top)..self.bot.min(scr_ht as f32).max(0.0) as u32
+    }
+
+    /// Returns the rows drawn by `render_walls` for the wall, including its top.
+    fn rows(&self, scr_ht: u32) -> Range<u32> {
+        first_row(self.cap_top)..self.face_rows(scr_ht).end
+    }
 }
 
 impl Raycaster {
-    /// Renders `map` from the POV of `camera` into `buf`.
-    pub fn render(&self, camera: &Camera, map: &Map, buf: &mut [u8]) {
+    /// Renders `map` and `sprites` from the POV of `camera` into `buf`.
+    ///
+    /// # Overall idea
+    ///
+    /// Rendering happens in 2 passes:
+    /// 1. A ray is cast for each column of the screen to find its wall
+    /// 2. The screen is split into horizontal strips of rows. Each strip gets
+    ///    its floor, roof, walls and sprites drawn independently
+    ///
+    /// Work in both the passes is spread across `threads` threads. Each thread
+    /// casts the rays of its chunk of columns, waits for the others to finish
+    /// and then draws its strip. Chunks of columns and strips don't overlap. So
+    /// the threads never write to the same memory.
+    ///
+    /// The columns (and their walls) are kept in `scratch` and reused in the
+    /// next frame.
+    ///
+    /// If there is `fog`, everything fades into its color with distance.
+    ///
+    /// Panics if the screen has no pixels.
+    pub fn render(&self, camera: &Camera, map: &Map, sprites: &[Sprite], buf: &mut [u8]) {
+        assert!(self.scr_wd > 0 && self.scr_ht > 0, "Screen has no pixels");
         assert_eq!(self.scr_wd * self.scr_ht * 4, buf.len() as u32);
 
-        self.render_floor(camera, buf);
-        self.render_walls(camera, map, buf);
+        let threads = self.threads.max(1);
+        let scr_wd = self.scr_wd as usize;
+        let max_ht = map.max_ht() * self.wall_ht_scale;
+
+        let mut chunks = self.scratch.columns.lock().unwrap();
+        let cols_per_thread = scr_wd.div_ceil(threads).max(1);
+        chunks.resize_with(scr_wd.div_ceil(cols_per_thread), RwLock::default);
+
+        for (i, chunk) in chunks.iter_mut().enumerate() {
+            let len = cols_per_thread.min(scr_wd - i * cols_per_thread);
+            chunk.get_mut().unwrap().resize_with(len, || Column {
+                ray_dir: camera.dir,
+                hits: Vec::new(),
+            });
+        }
+
+        let sprites = self.project_sprites(camera, map, sprites);
+
+        let row_len = 4 * scr_wd;
+        let rows_per_thread = (self.scr_ht as usize).div_ceil(threads).max(1);
+
+        let mut strips: Vec<_> = buf
+            .chunks_mut(rows_per_thread * row_len)
+            .map(Some)
+            .collect();
+        strips.resize_with(strips.len().max(chunks.len()), || None);
+
+        let chunks = &*chunks;
+        let barrier = Barrier::new(strips.len());
+
+        run_split(&mut strips, 1, |i, strip| {
+            if let Some(chunk) = chunks.get(i) {
+                let mut chunk = chunk.write().unwrap();
+                let x0 = (i * cols_per_thread) as u32;
+                self.cast_rays(camera, map, max_ht, x0, &mut chunk);
+            }
+
+            // Every strip needs the walls of all the columns.
+            barrier.wait();
+
+            if let Some(strip) = strip[0].as_deref_mut() {
+                let chunks: Vec<_> = chunks.iter().map(|chunk| chunk.read().unwrap()).collect();
+                let columns: Vec<_> = chunks.iter().flat_map(|chunk| chunk.iter()).collect();
+
+                let y0 = (i * rows_per_thread) as u32;
+                let rows = y0..(y0 + (strip.len() / row_len) as u32);
+
+                self.render_floor(camera, map, rows.clone(), strip);
+                self.render_walls(camera, &columns, rows.clone(), strip);
+                self.render_sprites(camera, &sprites, &columns, rows, strip);
+            }
+        });
     }
 
-    /// Renders the floor and roof. Both are made slightly darker for artistic
+    /// Renders the floor and roof for `rows` of the screen into `buf` (which
+    /// holds only those rows). Both are made slightly darker for artistic
     /// reasons.
     ///
     /// # Overall idea
@@ -31,106 +183,619 @@ impl Raycaster {
     /// So the question reduces to mapping screen's pixels to positions on the
     /// floor and roof.
     ///
-    /// As the camera is exactly at center of the screen, we can simply mirror
-    /// the floor to get the ceiling.
-    fn render_floor(&self, camera: &Camera, buf: &mut [u8]) {
+    /// Rows below the horizon show the floor and rows above it show the roof.
+    /// The distance of a row is inversely proportional to its distance from the
+    /// horizon. It is proportional to the camera's height above the floor (for
+    /// floor rows) or below the roof (for roof rows).
+    ///
+    /// Each cell of `map` can have its own floor and ceiling textures. Cells
+    /// without them (and positions outside the map) use the texture given by
+    /// `floor_id`.
+    ///
+    /// If there is a sky texture, outdoor cells and positions outside the map
+    /// show the sky instead of a roof. The sky wraps around the camera once.
+    /// So its column is decided by the angle of the ray through a column of
+    /// the screen. It spans a screen's height above the horizon.
+    fn render_floor(&self, camera: &Camera, map: &Map, rows: Range<u32>, buf: &mut [u8]) {
         let scr_wd = self.scr_wd as f32;
         let scr_ht = self.scr_ht as f32;
 
-        let tex = &self.textures[self.floor_id];
+        let horizon = self.horizon(camera);
+        let focal_ht = self.focal_ht(camera);
+        let eye_ht = self.eye_ht(camera);
+        let ray_ltmost = camera.ray(-1.0);
+        let ray_rtmost = camera.ray(1.0);
 
-        for y in (self.scr_ht / 2)..self.scr_ht {
-            let ray_ltmost = camera.ray(-1.0);
-            let ray_rtmost = camera.ray(1.0);
+        let sky = self.sky_id.map(|sky_id| &self.textures[sky_id]);
+        let sky_xs: Vec<_> = match sky {
+            Some(sky) => (0..self.scr_wd)
+                .map(|x| {
+                    let dir =
+                        ray_ltmost.dir + (ray_rtmost.dir - ray_ltmost.dir) * (x as f32 / scr_wd);
+                    let turns = (dir.y.atan2(dir.x) / (2.0 * PI)).rem_euclid(1.0);
+                    (sky.wd as f32 * turns) as usize % sky.wd
+                })
+                .collect(),
+            None => Vec::new(),
+        };
 
-            let cam_ht = scr_ht / 2.0;
-            let p = y as f32 - cam_ht;
-            let row_dist = cam_ht / p;
+        for y in rows.clone() {
+            // Rows are measured from their edge which is nearer to the horizon.
+            let is_floor = y as f32 >= horizon;
+            let row_dist = if is_floor {
+                eye_ht * focal_ht / (y as f32 - horizon)
+            } else {
+                let p = (horizon - (y + 1) as f32).max(0.0);
+                (self.wall_ht_scale - eye_ht) * focal_ht / p
+            };
 
             let mut pos = camera.pos + row_dist * ray_ltmost.dir;
             let step = row_dist * (ray_rtmost.dir - ray_ltmost.dir) / scr_wd;
 
+            // How much of a tile one pixel of the row covers sideways and in
+            // depth. Tiles are 1 unit wide.
+            let du = step.len();
+            let dv = if is_floor {
+                row_dist / (y as f32 - horizon).max(1.0)
+            } else {
+                row_dist / (horizon - (y + 1) as f32).max(1.0)
+            };
+
+            let layer = if is_floor { &map.floor } else { &map.ceil };
+            let darkness = if is_floor { 3 } else { 2 };
+            let fog_weight = self.fog_weight(row_dist);
+
+            let row_sky = sky.filter(|_| !is_floor);
+            let sky_y = row_sky.map_or(0, |sky| {
+                let pct = ((y as f32 - horizon + scr_ht) / scr_ht).max(0.0);
+                ((sky.ht as f32 * pct) as usize).min(sky.ht - 1)
+            });
+
             for x in 0..self.scr_wd {
-                let tex_x = (tex.wd as f32 * pos.x.fract()) as usize % tex.wd;
-                let tex_y = (tex.ht as f32 * pos.y.fract()) as usize % tex.ht;
+                let idx = map.cell_idx(pos.x, pos.y);
+                let i = 4 * (self.scr_wd * (y - rows.start) + x) as usize;
+
+                if let Some(sky) = row_sky.filter(|_| idx.is_none_or(|idx| map.outdoors[idx])) {
+                    let tex_i = 4 * (sky.wd * sky_y + sky_xs[x as usize]);
 
-                pos = pos + step;
+                    buf[i..i + 3].copy_from_slice(&sky.buf[tex_i..tex_i + 3]);
+                    buf[i + 3] = 255;
 
-                let i = 4 * (self.scr_wd * y + x) as usize;
-                let tex_i = 3 * (tex.wd * tex_y + tex_x);
+                    pos += step;
+                    continue;
+                }
 
-                buf[i + 0] = tex.buf[tex_i + 0] / 3;
-                buf[i + 1] = tex.buf[tex_i + 1] / 3;
-                buf[i + 2] = tex.buf[tex_i + 2] / 3;
-                buf[i + 3] = 255;
+                let tex_id = idx.and_then(|idx| layer[idx]);
+                let light = idx.map_or([FULL_LIGHT; 3], |idx| map.floor_light(idx));
 
-                let i = 4 * (self.scr_wd * (self.scr_ht - y - 1) + x) as usize;
+                let tex = self.texture(tex_id.map_or(self.floor_id, usize::from), du, dv);
+                let texel = self.sample_tile(tex, pos);
 
-                buf[i + 0] = tex.buf[tex_i + 0] / 2;
-                buf[i + 1] = tex.buf[tex_i + 1] / 2;
-                buf[i + 2] = tex.buf[tex_i + 2] / 2;
+                for c in 0..3 {
+                    let val = texel[c] / darkness;
+                    buf[i + c] = self.shade(val, c, light, fog_weight);
+                }
                 buf[i + 3] = 255;
+
+                pos += step;
             }
         }
     }
 
-    /// Renders all the walls. Walls along north-south axis have a darker tint
-    /// to fake rudimentary lighting.
+    /// Finds the walls visible in each column starting from column `x0`. The
+    /// results are stored in `columns`. `max_ht` is the height of the tallest
+    /// wall in `map` (after scaling).
     ///
     /// # Overall idea
     ///
     /// For each column in the screen:
-    /// 1. Cast a ray and note its intersection with a wall
-    /// 2. Calculate the perpendicular distance of the intersection point
-    /// 3. Greater the distance, smaller the wall's height for the column
-    ///
-    /// Camera is always at half-height of the scene. So, all walls have to be
-    /// vertically centered.
-    ///
-    /// Each column of the screen corresponds to a wall and is drawn using its
-    /// texture. 1 column of screen takes color from 1 column of texture.
+    /// 1. Cast a ray and note its intersections with walls
+    /// 2. Calculate the perpendicular distance of the intersection points
     ///
     /// As textures tile horizontally every 1 unit, we just use the fractional
     /// part of intersection point's  non-integral co-ordinate to decide which
     /// texture column to use.
-    fn render_walls(&self, camera: &Camera, map: &Map, buf: &mut [u8]) {
+    ///
+    /// For picking mip levels, we also need how far apart the hits of adjacent
+    /// columns are along the wall. It grows with distance and as the wall
+    /// turns away from the camera.
+    ///
+    /// Walls can be of different heights. So there can be walls visible above
+    /// a nearer wall. The ray goes on until the walls found so far hide
+    /// everything behind them. That's when even a wall as tall as the tallest
+    /// one in `map`, placed right where the ray leaves the last wall's cell,
+    /// would be hidden. In maps whose walls are all equally tall, that happens
+    /// at the first wall.
+    ///
+    /// Columns whose rays leave the map (or go farther than `max_dist`) without
+    /// hitting a wall don't get any. Walls along north-south axis have a darker
+    /// tint to fake rudimentary lighting.
+    fn cast_rays(&self, camera: &Camera, map: &Map, max_ht: f32, x0: u32, columns: &mut [Column]) {
         let scr_wd = self.scr_wd as f32;
-        let scr_ht = self.scr_ht as f32;
+        let plane_step = 2.0 * camera.plane_len() / scr_wd;
+        let horizon = self.horizon(camera);
+        let focal_ht = self.focal_ht(camera);
+        let eye_ht = self.eye_ht(camera);
 
-        for x in 0..self.scr_wd {
+        for (x, column) in (x0..).zip(columns.iter_mut()) {
             let pct = 2.0 * (x as f32 - scr_wd / 2.0) / scr_wd;
             let ray = camera.ray(pct);
-            let intersection = map.intersect(&ray);
-
             let cos = ray.dir.dot(&camera.dir);
-            let perp_dist = (intersection.pos - camera.pos).len() * cos;
-            let wall_ht = self.wall_ht_scale * scr_ht / perp_dist;
-            let offs = ((scr_ht - wall_ht) / 2.0).max(0.0);
 
-            let wall_top = offs as u32;
-            let wall_bot = (scr_ht - offs) as u32;
+            column.ray_dir = ray.dir / cos;
+            column.hits.clear();
 
-            let tex = &self.textures[intersection.tex as usize];
-            let tex_x = if intersection.in_ns_dir {
-                (tex.wd as f32 * intersection.pos.y.fract()) as usize
-            } else {
-                (tex.wd as f32 * intersection.pos.x.fract()) as usize
-            };
+            // Rows from here down are hidden by the walls found so far.
+            let mut clip = self.scr_ht as f32;
 
-            let tex_darkness = if intersection.in_ns_dir { 2 } else { 1 };
+            for intersection in map.hits(&ray, self.max_dist) {
+                let perp_dist = (intersection.pos - camera.pos).len() * cos;
+                let exit_dist = (intersection.exit - camera.pos).len() * cos;
+                let facing = ray.dir.dot(&intersection.face.normal()).abs();
+                let ht = intersection.ht * self.wall_ht_scale;
 
-            for y in 0..self.scr_ht {
-                if wall_top < y && y < wall_bot {
-                    let i = 4 * (self.scr_wd * y + x) as usize;
-                    let tex_y = (tex.ht as f32 * (y - wall_top) as f32 / wall_ht) as usize;
-                    let tex_i = 3 * (tex.wd * tex_y + tex_x);
+                let top = horizon - (ht - eye_ht) * focal_ht / perp_dist;
+                let cap_top = if ht < eye_ht {
+                    horizon + (eye_ht - ht) * focal_ht / exit_dist
+                } else {
+                    top
+                };
 
-                    buf[i + 0] = tex.buf[tex_i + 0] / tex_darkness;
-                    buf[i + 1] = tex.buf[tex_i + 1] / tex_darkness;
-                    buf[i + 2] = tex.buf[tex_i + 2] / tex_darkness;
+                column.hits.push(Hit {
+                    perp_dist,
+                    exit_dist,
+                    ht,
+                    top,
+                    bot: horizon + eye_ht * focal_ht / perp_dist,
+                    cap_top,
+                    tex: intersection.tex as usize,
+                    tex_x: intersection.tex_x,
+                    tex_du: plane_step * perp_dist * cos / facing,
+                    darkness: if intersection.in_ns_dir { 2 } else { 1 },
+                    light: map.face_light(intersection.idx, intersection.face),
+                });
+
+                // Walls farther away appear lower than their height says.
+                // Those lower than the camera appear no higher than the horizon.
+                clip = clip.min(cap_top);
+                let farther_top = horizon - (max_ht - eye_ht).max(0.0) * focal_ht / exit_dist;
+
+                if clip <= 0.0 || farther_top >= clip {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Renders the walls found by `cast_rays` for `rows` of the screen into
+    /// `buf` (which holds only those rows).
+    ///
+    /// # Overall idea
+    ///
+    /// Greater the distance of a column's wall, smaller its height. A wall at
+    /// unit distance is as tall as the camera plane is high (see
+    /// `Camera::plane_ht`). This keeps the proportions of things right on
+    /// screens of any aspect ratio.
+    ///
+    /// Walls stand on the floor and are as tall as their cells say (scaled by
+    /// `wall_ht_scale`). Where they appear vertically depends upon the horizon
+    /// (decided by the camera's pitch) and the camera's height above the floor.
+    ///
+    /// Each column of the screen corresponds to a wall and is drawn using its
+    /// texture. 1 column of screen takes color from 1 column of texture. The
+    /// texture repeats every `wall_ht_scale` units of height and lines up with
+    /// the floor. So walls of any height look like a stack of unit walls cut
+    /// off at the top.
+    ///
+    /// Walls of a column are drawn from the nearest to the farthest. Each one
+    /// only gets the rows above the ones taken by nearer walls. Walls lower
+    /// than the camera also show their top, using the same texture as their
+    /// faces.
+    ///
+    /// Columns without walls are left alone. The ceiling and floor drawn by
+    /// `render_floor` show instead.
+    fn render_walls(&self, camera: &Camera, columns: &[&Column], rows: Range<u32>, buf: &mut [u8]) {
+        let horizon = self.horizon(camera);
+        let focal_ht = self.focal_ht(camera);
+        let eye_ht = self.eye_ht(camera);
+
+        for (x, column) in (0..self.scr_wd).zip(columns) {
+            // Rows from here down are taken by nearer walls.
+            let mut clip = self.scr_ht;
+
+            for hit in &column.hits {
+                let face_rows = hit.face_rows(self.scr_ht);
+                let cap_rows = first_row(hit.cap_top)..face_rows.start;
+
+                let y_end = face_rows.end.min(clip).min(rows.end);
+                let y_range = face_rows.start.max(rows.start)..y_end;
+                self.render_face(camera, hit, x, y_range, rows.start, buf);
+
+                let y_end = cap_rows.end.min(clip).min(rows.end);
+                for y in cap_rows.start.max(rows.start)..y_end {
+                    // Distance of the point on the top seen through row `y`.
+                    let dist = (eye_ht - hit.ht) * focal_ht / (y as f32 - horizon).max(1.0);
+                    let dist = dist.clamp(hit.perp_dist, hit.exit_dist);
+                    let pos = camera.pos + dist * column.ray_dir;
+
+                    let tex = self.texture(hit.tex, dist / focal_ht, dist / focal_ht);
+                    let texel = self.sample_tile(tex, pos);
+                    let fog_weight = self.fog_weight(dist);
+
+                    let i = 4 * (self.scr_wd * (y - rows.start) + x) as usize;
+                    for c in 0..3 {
+                        buf[i + c] = self.shade(texel[c], c, hit.light, fog_weight);
+                    }
                     buf[i + 3] = 255;
                 }
+
+                clip = clip.min(cap_rows.start);
+            }
+        }
+    }
+
+    /// Renders `rows` of column `x` with the face of the wall hit by `hit`.
+    /// `buf` holds the rows starting at `y0`.
+    fn render_face(
+        &self,
+        camera: &Camera,
+        hit: &Hit,
+        x: u32,
+        rows: Range<u32>,
+        y0: u32,
+        buf: &mut [u8],
+    ) {
+        let focal_ht = self.focal_ht(camera);
+
+        // Height of the wall in rows and the height of a tile of its texture.
+        let wall_ht = hit.ht * focal_ht / hit.perp_dist;
+        let tile_ht = self.wall_ht_scale * focal_ht / hit.perp_dist;
+        // Part of a tile cut off at the top and the number of tiles visible.
+        let offs = (1.0 - wall_ht / tile_ht).rem_euclid(1.0);
+        let tiles = (offs + wall_ht / tile_ht).ceil().max(1.0) as usize;
+
+        let tex = self.texture(hit.tex, hit.tex_du, 1.0 / tile_ht);
+        let fog_weight = self.fog_weight(hit.perp_dist);
+
+        let tex_x = (tex.wd as f32 * hit.tex_x) as usize;
+
+        for y in rows {
+            let texel = match self.sampling {
+                Sampling::Nearest => {
+                    let v = offs + (y as f32 - hit.top.floor()) / tile_ht;
+                    let tex_y = ((tex.ht as f32 * v) as usize).min(tiles * tex.ht - 1);
+                    texel_at(tex, tex_x, tex_y % tex.ht)
+                }
+                Sampling::Bilinear => {
+                    // Walls don't repeat past their top and bottom. So blending
+                    // stops half a texel short of them. Small mip levels of low
+                    // walls can have less than a texel between those limits.
+                    // And `offs` is NaN right next to a wall. Hence the
+                    // NaN-tolerant `max` and `min` instead of `clamp`.
+                    let v = offs + (y as f32 + 0.5 - hit.top) / tile_ht;
+                    let hi = (tiles * tex.ht) as f32 - 0.5;
+                    let lo = (tex.ht as f32 * offs + 0.5).min(hi);
+                    let tex_y = (tex.ht as f32 * v).max(lo).min(hi);
+                    tex.bilinear(tex.wd as f32 * hit.tex_x, tex_y)
+                }
+            };
+
+            let i = 4 * (self.scr_wd * (y - y0) + x) as usize;
+            for c in 0..3 {
+                let val = texel[c] / hit.darkness;
+                buf[i + c] = self.shade(val, c, hit.light, fog_weight);
             }
+            buf[i + 3] = 255;
+        }
+    }
+
+    /// Transforms `sprites` into camera space and finds the light falling on
+    /// them. Sprites behind the camera are dropped. The rest are sorted from
+    /// the farthest to the nearest.
+    fn project_sprites<'a>(
+        &self,
+        camera: &Camera,
+        map: &Map,
+        sprites: &'a [Sprite],
+    ) -> Vec<(&'a Sprite, Vec2, LightVal)> {
+        let mut projected: Vec<_> = sprites
+            .iter()
+            .map(|sprite| (sprite, camera.to_cam_space(sprite.pos)))
+            .filter(|(_, cam_pos)| cam_pos.y > 0.0)
+            .map(|(sprite, cam_pos)| {
+                let idx = map.cell_idx(sprite.pos.x, sprite.pos.y);
+                let light = idx.map_or([FULL_LIGHT; 3], |idx| map.floor_light(idx));
+                (sprite, cam_pos, light)
+            })
+            .collect();
+
+        projected.sort_by(|(_, a, _), (_, b, _)| b.y.partial_cmp(&a.y).unwrap_or(Ordering::Equal));
+        projected
+    }
+
+    /// Renders the sprites found by `project_sprites` for `rows` of the screen
+    /// into `buf` (which holds only those rows). Sprites which are hidden
+    /// behind walls aren't drawn. Neither are the transparent parts of their
+    /// textures.
+    ///
+    /// # Overall idea
+    ///
+    /// The camera space position of a sprite tells us the column where its
+    /// center lies and its perpendicular distance. Like for walls, greater the
+    /// distance, smaller the sprite.
+    ///
+    /// Sprites are drawn from the farthest to the nearest. This way the nearer
+    /// sprites are drawn over the farther ones. A pixel of a sprite isn't drawn
+    /// if a nearer wall in that column (given by `columns`) covers it.
+    fn render_sprites(
+        &self,
+        camera: &Camera,
+        sprites: &[(&Sprite, Vec2, LightVal)],
+        columns: &[&Column],
+        rows: Range<u32>,
+        buf: &mut [u8],
+    ) {
+        let scr_wd = self.scr_wd as f32;
+        let scr_ht = self.scr_ht as f32;
+
+        let horizon = self.horizon(camera);
+        let focal_ht = self.focal_ht(camera);
+        let eye_ht = self.eye_ht(camera);
+
+        // Rows taken by walls nearer than the sprite in the current column.
+        let mut nearer = Vec::new();
+
+        for (sprite, cam_pos, light) in sprites {
+            let tex = &self.textures[sprite.tex as usize];
+            let fog_weight = self.fog_weight(cam_pos.y);
+
+            // Sprites are 1 unit wide. So they are exactly as wide as walls at
+            // the same distance.
+            let sprite_wd = scr_wd / (2.0 * camera.plane_len() * cam_pos.y);
+            let scale = focal_ht / cam_pos.y;
+            let sprite_ht = self.wall_ht_scale * scale;
+            let center_x = scr_wd / 2.0 * (1.0 + cam_pos.x);
+
+            // Like walls, sprites stand on the floor.
+            let sprite_lt = center_x - sprite_wd / 2.0;
+            let sprite_top = horizon - (self.wall_ht_scale - eye_ht) * scale;
+
+            let lt = sprite_lt.max(0.0) as u32;
+            let rt = (sprite_lt + sprite_wd).min(scr_wd).max(0.0) as u32;
+            let top = sprite_top.max(0.0) as u32;
+            let bot = (sprite_top + sprite_ht).min(scr_ht).max(0.0) as u32;
+
+            for x in lt..rt {
+                nearer.clear();
+                nearer.extend(
+                    columns[x as usize]
+                        .hits
+                        .iter()
+                        .take_while(|hit| hit.perp_dist <= cam_pos.y)
+                        .map(|hit| hit.rows(self.scr_ht)),
+                );
+
+                let tex_x = (tex.wd as f32 * (x as f32 - sprite_lt) / sprite_wd) as usize;
+                let tex_x = tex_x.min(tex.wd - 1);
+
+                for y in top.max(rows.start)..bot.min(rows.end) {
+                    if nearer.iter().any(|rows| rows.contains(&y)) {
+                        continue;
+                    }
+
+                    let tex_y = (tex.ht as f32 * (y as f32 - sprite_top) / sprite_ht) as usize;
+                    let tex_y = tex_y.min(tex.ht - 1);
+
+                    let i = 4 * (self.scr_wd * (y - rows.start) + x) as usize;
+                    let tex_i = 4 * (tex.wd * tex_y + tex_x);
+
+                    let alpha = tex.buf[tex_i + 3];
+
+                    if alpha == 0 {
+                        continue;
+                    }
+
+                    for c in 0..3 {
+                        let src = self.shade(tex.buf[tex_i + c], c, *light, fog_weight);
+                        let src = src as u32 * alpha as u32;
+                        let dst = buf[i + c] as u32 * (255 - alpha as u32);
+                        buf[i + c] = ((src + dst) / 255) as u8;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the color of `tex` at `pos` on a floor (or something like it)
+    /// on which `tex` repeats every unit.
+    fn sample_tile(&self, tex: &Texture, pos: Vec2) -> [u8; 4] {
+        match self.sampling {
+            Sampling::Nearest => {
+                let tex_x = (tex.wd as f32 * pos.x.fract()) as usize % tex.wd;
+                let tex_y = (tex.ht as f32 * pos.y.fract()) as usize % tex.ht;
+                texel_at(tex, tex_x, tex_y)
+            }
+            Sampling::Bilinear => {
+                tex.bilinear(tex.wd as f32 * pos.x.fract(), tex.ht as f32 * pos.y.fract())
+            }
+        }
+    }
+
+    /// Returns the texture with index `id`, or its mip level for pixels which
+    /// cover `du` and `dv` of it if `mipmaps` is on.
+    fn texture(&self, id: usize, du: f32, dv: f32) -> &Texture {
+        let tex = &self.textures[id];
+
+        if self.mipmaps {
+            tex.mip(du, dv)
+        } else {
+            tex
+        }
+    }
+
+    /// Returns how much fog shows at perpendicular distance `dist`. See
+    /// `Fog::weight`.
+    fn fog_weight(&self, dist: f32) -> u32 {
+        self.fog.map_or(0, |fog| fog.weight(dist))
+    }
+
+    /// Scales channel `c` of a color by `light` and then fades it towards the
+    /// fog's color.
+    fn shade(&self, val: u8, c: usize, light: LightVal, fog_weight: u32) -> u8 {
+        let val = (val as u32 * light[c] as u32 / FULL_LIGHT as u32).min(255) as u8;
+
+        match self.fog {
+            Some(fog) => fog.blend(val, c, fog_weight),
+            None => val,
         }
     }
+
+    /// Returns how many rows tall something 1 unit tall looks at unit distance
+    /// from the camera.
+    fn focal_ht(&self, camera: &Camera) -> f32 {
+        self.scr_ht as f32 / (2.0 * camera.plane_ht())
+    }
+
+    /// Returns the camera's height above the floor after scaling by
+    /// `wall_ht_scale`. See `Camera::eye_ht`.
+    fn eye_ht(&self, camera: &Camera) -> f32 {
+        self.wall_ht_scale * camera.eye_ht
+    }
+
+    /// Returns the screen row of the horizon. It is at the center of the
+    /// screen unless the camera is pitched up or down.
+    fn horizon(&self, camera: &Camera) -> f32 {
+        self.scr_ht as f32 * (0.5 + camera.pitch)
+    }
+}
+
+/// Returns the first row drawn for something whose top is at row `top`. The
+/// row at `top` itself isn't drawn unless it is off screen.
+///
+/// `top` is infinite for the top of a wall lower than the camera when the
+/// camera touches the wall. It saturates to the last row then.
+fn first_row(top: f32) -> u32 {
+    if top < 0.0 {
+        0
+    } else {
+        (top as u32).saturating_add(1)
+    }
+}
+
+/// Returns the texel at (`x`, `y`) of `tex`.
+fn texel_at(tex: &Texture, x: usize, y: usize) -> [u8; 4] {
+    let i = 4 * (tex.wd * y + x);
+    [tex.buf[i], tex.buf[i + 1], tex.buf[i + 2], tex.buf[i + 3]]
+}
+
+/// Splits `items` into chunks of `chunk_len` and calls `f` with the index and
+/// contents of each chunk. Each chunk except the last gets its own thread. The
+/// last one is handled by the calling thread.
+fn run_split<T, F>(items: &mut [T], chunk_len: usize, f: F)
+where
+    T: Send,
+    F: Fn(usize, &mut [T]) + Sync,
+{
+    let mut chunks: Vec<_> = items.chunks_mut(chunk_len).enumerate().collect();
+    let last = chunks.pop();
+
+    thread::scope(|scope| {
+        for (i, chunk) in chunks {
+            let f = &f;
+            scope.spawn(move || f(i, chunk));
+        }
+
+        if let Some((i, chunk)) = last {
+            f(i, chunk);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_caster() -> Raycaster {
+        Raycaster {
+            scr_wd: 64,
+            scr_ht: 48,
+            textures: Arc::new(TextureRegistry::new()),
+            floor_id: 0,
+            wall_ht_scale: 1.0,
+            max_dist: 100.0,
+            threads: 1,
+            fog: None,
+            sky_id: None,
+            mipmaps: false,
+            sampling: Sampling::Nearest,
+            scratch: Scratch::default(),
+        }
+    }
+
+    /// Returns a closed 12x12 map. The camera is in the space along its west
+    /// side. The rest of the map is a solid block of walls.
+    fn make_map() -> Map {
+        let grid = (0..12 * 12)
+            .map(|i| {
+                let (x, y) = (i % 12, i / 12);
+                (x == 0 || y == 0 || x >= 4 || y == 11).then_some(0)
+            })
+            .collect();
+
+        Map::new(12, 12, grid)
+    }
+
+    fn cast_rays(map: &Map) -> Vec<Column> {
+        let camera = Camera::new(Vec2::new(2.5, 6.5), Vec2::new(1.0, 0.0), 90.0);
+        cast_rays_from(map, &camera)
+    }
+
+    fn cast_rays_from(map: &Map, camera: &Camera) -> Vec<Column> {
+        let caster = make_caster();
+        let mut columns: Vec<_> = (0..caster.scr_wd)
+            .map(|_| Column {
+                ray_dir: camera.dir,
+                hits: Vec::new(),
+            })
+            .collect();
+
+        caster.cast_rays(camera, map, map.max_ht(), 0, &mut columns);
+        columns
+    }
+
+    #[test]
+    fn cast_rays_stops_at_first_wall_of_uniform_height() {
+        let columns = cast_rays(&make_map());
+
+        assert!(columns.iter().all(|column| column.hits.len() == 1));
+    }
+
+    #[test]
+    fn cast_rays_stops_once_tower_cant_be_seen() {
+        // A tower in a corner behind the camera.
+        let mut map = make_map();
+        map.heights[0] = 1.5;
+
+        let columns = cast_rays(&map);
+
+        // The tower could be seen over the 1st wall if it stood right behind
+        // it. Rays go on for a few walls, but not through the 8 cells deep
+        // block.
+        assert!(columns.iter().all(|column| column.hits.len() <= 4));
+    }
+
+    #[test]
+    fn cast_rays_given_camera_touching_lower_wall() {
+        // The eye is above the wall and the camera is right at its face. So the
+        // wall's top is infinitely far down the screen.
+        let mut camera = Camera::new(Vec2::new(4.0, 6.5), Vec2::new(1.0, 0.0), 90.0);
+        camera.eye_ht = 1.1;
+
+        let columns = cast_rays_from(&make_map(), &camera);
+        let hit = &columns[32].hits[0];
+
+        assert_eq!(hit.perp_dist, 0.0);
+        assert!(hit.face_rows(48).is_empty());
+        assert!(hit.rows(48).end <= 48);
+    }
 }
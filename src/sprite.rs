@@ -0,0 +1,23 @@
+use serde::Deserialize;
+
+use crate::map::TexId;
+use crate::primitive::Vec2;
+
+/// Represents a billboard placed in the world. Stuff like barrels, lamps etc.
+///
+/// A sprite is always drawn facing the camera. It stands on the floor and is
+/// as tall as a wall. Its texture should be RGBA so that the space around the
+/// actual object can be transparent.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct Sprite {
+    pub pos: Vec2,
+    /// Must be the id of a loaded texture. `Map::check` makes sure of that for
+    /// sprites in the config.
+    pub tex: TexId,
+}
+
+impl Sprite {
+    pub fn new(pos: Vec2, tex: TexId) -> Self {
+        Sprite { pos, tex }
+    }
+}
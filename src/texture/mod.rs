@@ -0,0 +1,396 @@
+mod registry;
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use png::{BitDepth, ColorType, Decoder, Transformations};
+use serde::Deserialize;
+use thiserror::Error;
+
+pub use registry::{RegistryLoadError, TextureRegistry, MAX_TEX_SIZE};
+
+/// Represents a texture which can be used to draw stuff like walls, roof etc.
+///
+/// It is simply a collection of byte values. Every pixel takes 4 bytes which
+/// are its red, green, blue and alpha values, irrespective of the layout of the
+/// source image file.
+///
+/// Each texture also keeps a mip chain: copies of itself at half, a quarter
+/// etc. of its size, down to 1x1. See `Texture::mip`.
+pub struct Texture {
+    pub wd: usize,
+    pub ht: usize,
+    pub buf: Vec<u8>,
+    /// Mip levels 1 and up. Level 0 is the texture itself.
+    mips: Vec<Texture>,
+}
+
+/// How a color is picked from a texture for a position which falls between
+/// its texels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sampling {
+    /// The texel containing the position is used as is. Gives textures a
+    /// blocky, retro look up close.
+    #[default]
+    Nearest,
+    /// The 4 texels nearest to the position are blended by how close they
+    /// are. Smooth up close, but slower.
+    Bilinear,
+}
+
+#[derive(Debug, Error)]
+pub enum TextureLoadError {
+    #[error("Couldn't open texture")]
+    IoError(#[from] io::Error),
+    #[error("Error in decoding png")]
+    DecodingError(#[from] png::DecodingError),
+    #[error("Texture is {wd}x{ht}. Textures must be non-empty and at most {max}x{max}", max = MAX_TEX_SIZE)]
+    BadSize { wd: usize, ht: usize },
+    #[error("Unsupported png format {color_type:?} with {bits} bits per sample")]
+    UnsupportedFormat { color_type: ColorType, bits: u8 },
+}
+
+/// Returns the name by which maps refer to the texture at `path`. It is simply
+/// the file name without the extension e.g `red_brick` for `res/red_brick.png`.
+pub fn tex_name<P: AsRef<Path>>(path: P) -> String {
+    path.as_ref()
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+impl Texture {
+    /// Loads a `Texture` from `path`. See `Texture::decode`.
+    pub fn load<P: AsRef<Path> + fmt::Debug>(path: P) -> Result<Self, TextureLoadError> {
+        info!("Loading texture at {:?}", path);
+
+        Texture::decode(File::open(path)?)
+    }
+
+    /// Decodes a PNG image into a `Texture`.
+    ///
+    /// Grayscale, RGB, indexed (palette) and RGBA images of any bit depth are
+    /// supported. They are all converted to 8 bit RGBA. Images without alpha
+    /// are fully opaque, except for colors marked transparent by a `tRNS`
+    /// chunk.
+    pub fn decode<R: Read>(src: R) -> Result<Self, TextureLoadError> {
+        let mut decoder = Decoder::new(src);
+        // Expand palettes, low bit depths and tRNS to plain samples and strip
+        // 16 bit samples to 8 bits.
+        decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+
+        let (info, mut reader) = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf)?;
+
+        let (color_type, bits) = reader.output_color_type();
+
+        info!(
+            "Texture is {:?} with {:?} bits",
+            info.color_type, info.bit_depth
+        );
+
+        if bits != BitDepth::Eight {
+            return Err(TextureLoadError::UnsupportedFormat {
+                color_type,
+                bits: bits as u8,
+            });
+        }
+
+        let buf = match color_type {
+            ColorType::RGBA => buf,
+            ColorType::RGB => buf
+                .chunks_exact(3)
+                .flat_map(|px| [px[0], px[1], px[2], 255])
+                .collect(),
+            ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .flat_map(|px| [px[0], px[0], px[0], px[1]])
+                .collect(),
+            ColorType::Grayscale => buf.iter().flat_map(|&val| [val, val, val, 255]).collect(),
+            ColorType::Indexed => {
+                return Err(TextureLoadError::UnsupportedFormat {
+                    color_type,
+                    bits: bits as u8,
+                })
+            }
+        };
+
+        Ok(Texture::new(info.width as usize, info.height as usize, buf))
+    }
+
+    /// Creates a `Texture` from RGBA pixels and generates its mip chain.
+    pub fn new(wd: usize, ht: usize, buf: Vec<u8>) -> Self {
+        assert_eq!(wd * ht * 4, buf.len());
+
+        let mut tex = Texture {
+            wd,
+            ht,
+            buf,
+            mips: Vec::new(),
+        };
+
+        let mut prev = &tex;
+        let mut mips = Vec::new();
+
+        while prev.wd * prev.ht > 1 {
+            mips.push(prev.downsample());
+            prev = mips.last().unwrap();
+        }
+
+        tex.mips = mips;
+        tex
+    }
+
+    /// Returns the number of mip levels, including level 0.
+    pub fn levels(&self) -> usize {
+        self.mips.len() + 1
+    }
+
+    /// Returns the mip level best suited for drawing a pixel which covers `du`
+    /// of the texture's width and `dv` of its height. Both are fractions of the
+    /// texture's size.
+    ///
+    /// # Overall idea
+    ///
+    /// When a pixel covers several texels, sampling a single one of them makes
+    /// the texture shimmer as the camera moves. Each level has half the size of
+    /// the previous one and each of its texels is the average of 2x2 texels of
+    /// the previous one. So the level whose texels are about as large as the
+    /// pixel is picked. The larger of `du` and `dv` decides which one that is.
+    pub fn mip(&self, du: f32, dv: f32) -> &Texture {
+        let texels = (self.wd as f32 * du).max(self.ht as f32 * dv);
+
+        // `texels as usize` is 0 for NaN and saturates for infinity.
+        let texels = texels as usize;
+        let level = match texels {
+            0 => 0,
+            _ => (usize::BITS - 1 - texels.leading_zeros()) as usize,
+        };
+
+        // A 1x1 texture has no smaller levels, so it is its own mip.
+        match level.min(self.mips.len()) {
+            0 => self,
+            level => &self.mips[level - 1],
+        }
+    }
+
+    /// Returns the color at (`x`, `y`) blended from the 4 texels whose centers
+    /// are nearest to it. Both are measured in texels, so the center of the
+    /// texel at (0, 0) is at (0.5, 0.5). The texture repeats in both
+    /// directions, so positions near an edge blend with the opposite edge.
+    pub fn bilinear(&self, x: f32, y: f32) -> [u8; 4] {
+        let x = x - 0.5;
+        let y = y - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let x0 = (x0 as i64).rem_euclid(self.wd as i64) as usize;
+        let y0 = (y0 as i64).rem_euclid(self.ht as i64) as usize;
+        let x1 = (x0 + 1) % self.wd;
+        let y1 = (y0 + 1) % self.ht;
+
+        let texel = |x: usize, y: usize, c: usize| self.buf[4 * (self.wd * y + x) + c] as f32;
+
+        let mut res = [0; 4];
+
+        for (c, val) in res.iter_mut().enumerate() {
+            let top = texel(x0, y0, c) * (1.0 - fx) + texel(x1, y0, c) * fx;
+            let bot = texel(x0, y1, c) * (1.0 - fx) + texel(x1, y1, c) * fx;
+            *val = (top * (1.0 - fy) + bot * fy).round() as u8;
+        }
+
+        res
+    }
+
+    /// Returns a copy of the texture at half its size (but at least 1x1). Each
+    /// texel is the average of the 2x2 texels it covers. Odd rows and columns
+    /// at the ends are dropped.
+    fn downsample(&self) -> Texture {
+        let wd = (self.wd / 2).max(1);
+        let ht = (self.ht / 2).max(1);
+        let mut buf = Vec::with_capacity(wd * ht * 4);
+
+        for y in 0..ht {
+            let ys = [2 * y, (2 * y + 1).min(self.ht - 1)];
+
+            for x in 0..wd {
+                let xs = [2 * x, (2 * x + 1).min(self.wd - 1)];
+
+                for c in 0..4 {
+                    let mut sum = 0;
+
+                    for y in ys {
+                        for x in xs {
+                            sum += self.buf[4 * (self.wd * y + x) + c] as u32;
+                        }
+                    }
+
+                    buf.push(((sum + 2) / 4) as u8);
+                }
+            }
+        }
+
+        Texture {
+            wd,
+            ht,
+            buf,
+            mips: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes a 2x1 PNG with `data` as its pixels.
+    fn encode(
+        color_type: ColorType,
+        depth: BitDepth,
+        data: &[u8],
+        palette: Option<Vec<u8>>,
+    ) -> Vec<u8> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, 2, 1);
+        encoder.set_color(color_type);
+        encoder.set_depth(depth);
+
+        if let Some(palette) = palette {
+            encoder.set_palette(palette);
+        }
+
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+        drop(writer);
+        png
+    }
+
+    fn decode(png: Vec<u8>) -> Vec<u8> {
+        let tex = Texture::decode(png.as_slice()).unwrap();
+        assert_eq!((tex.wd, tex.ht), (2, 1));
+        tex.buf
+    }
+
+    #[test]
+    fn decode_converts_rgb_to_rgba() {
+        let png = encode(ColorType::RGB, BitDepth::Eight, &[1, 2, 3, 4, 5, 6], None);
+        assert_eq!(decode(png), [1, 2, 3, 255, 4, 5, 6, 255]);
+    }
+
+    #[test]
+    fn decode_keeps_alpha() {
+        let data = [1, 2, 3, 0, 4, 5, 6, 128];
+        let png = encode(ColorType::RGBA, BitDepth::Eight, &data, None);
+        assert_eq!(decode(png), data);
+    }
+
+    #[test]
+    fn decode_converts_grayscale() {
+        let png = encode(ColorType::Grayscale, BitDepth::Eight, &[10, 20], None);
+        assert_eq!(decode(png), [10, 10, 10, 255, 20, 20, 20, 255]);
+
+        let png = encode(
+            ColorType::GrayscaleAlpha,
+            BitDepth::Eight,
+            &[10, 0, 20, 99],
+            None,
+        );
+        assert_eq!(decode(png), [10, 10, 10, 0, 20, 20, 20, 99]);
+    }
+
+    #[test]
+    fn decode_expands_palettes() {
+        // 4 bits per index. The indices are 1 and 0.
+        let palette = vec![7, 8, 9, 70, 80, 90];
+        let png = encode(ColorType::Indexed, BitDepth::Four, &[0x10], Some(palette));
+        assert_eq!(decode(png), [70, 80, 90, 255, 7, 8, 9, 255]);
+    }
+
+    #[test]
+    fn decode_strips_16_bit_samples() {
+        let data = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0];
+        let png = encode(ColorType::GrayscaleAlpha, BitDepth::Sixteen, &data, None);
+        assert_eq!(
+            decode(png),
+            [0x12, 0x12, 0x12, 0x56, 0x9a, 0x9a, 0x9a, 0xde]
+        );
+    }
+
+    #[test]
+    fn new_builds_mip_chain_down_to_1x1() {
+        let tex = Texture::new(4, 2, vec![0; 4 * 2 * 4]);
+        assert_eq!(tex.levels(), 3);
+
+        let sizes: Vec<_> = (0..3)
+            .map(|level| tex.mip((1 << level) as f32 / 4.0, 0.0))
+            .map(|mip| (mip.wd, mip.ht))
+            .collect();
+        assert_eq!(sizes, [(4, 2), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn downsample_averages_texels() {
+        #[rustfmt::skip]
+        let buf = vec![
+            0, 0, 0, 255,   100, 0, 0, 255,
+            0, 100, 0, 255, 100, 100, 0, 0,
+        ];
+        let tex = Texture::new(2, 2, buf);
+
+        assert_eq!(tex.mip(1.0, 1.0).buf, [50, 50, 0, 191]);
+    }
+
+    #[test]
+    fn mip_picks_level_by_texels_per_pixel() {
+        let tex = Texture::new(64, 64, vec![0; 64 * 64 * 4]);
+
+        assert_eq!(tex.mip(0.0, 0.0).wd, 64);
+        assert_eq!(tex.mip(1.0 / 64.0, 0.0).wd, 64);
+        assert_eq!(tex.mip(3.0 / 64.0, 0.0).wd, 32);
+        assert_eq!(tex.mip(0.0, 4.0 / 64.0).wd, 16);
+        assert_eq!(tex.mip(f32::INFINITY, 0.0).wd, 1);
+    }
+
+    #[test]
+    fn mip_of_1x1_texture_is_itself() {
+        let tex = Texture::new(1, 1, vec![1, 2, 3, 255]);
+
+        assert_eq!(tex.levels(), 1);
+        assert_eq!(tex.mip(4.0, 4.0).buf, [1, 2, 3, 255]);
+        assert_eq!(tex.mip(f32::INFINITY, f32::INFINITY).wd, 1);
+    }
+
+    #[test]
+    fn bilinear_blends_nearest_texels() {
+        #[rustfmt::skip]
+        let buf = vec![
+            0, 0, 0, 0,     100, 0, 0, 0,
+            0, 200, 0, 0,   100, 200, 0, 0,
+        ];
+        let tex = Texture::new(2, 2, buf);
+
+        assert_eq!(tex.bilinear(0.5, 0.5), [0, 0, 0, 0]);
+        assert_eq!(tex.bilinear(1.5, 1.5), [100, 200, 0, 0]);
+        assert_eq!(tex.bilinear(1.0, 1.0), [50, 100, 0, 0]);
+        assert_eq!(tex.bilinear(1.25, 0.5), [75, 0, 0, 0]);
+    }
+
+    #[test]
+    fn bilinear_wraps_around_edges() {
+        let tex = Texture::new(2, 1, vec![0, 0, 0, 0, 100, 0, 0, 0]);
+
+        assert_eq!(tex.bilinear(0.0, 0.5), [50, 0, 0, 0]);
+        assert_eq!(tex.bilinear(2.0, 0.5), [50, 0, 0, 0]);
+        assert_eq!(tex.bilinear(-0.5, 0.5), [100, 0, 0, 0]);
+    }
+
+    #[test]
+    fn decode_rejects_garbage() {
+        let res = Texture::decode(&b"not a png"[..]);
+        assert!(matches!(res, Err(TextureLoadError::DecodingError(_))));
+    }
+}
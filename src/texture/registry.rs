@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::ops::Index;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use super::{tex_name, Texture, TextureLoadError};
+
+/// Max width and height of a texture.
+pub const MAX_TEX_SIZE: usize = 4096;
+
+/// Returned when a texture in a `TextureRegistry` can't be loaded.
+#[derive(Debug, Error)]
+#[error("{path:?}: {source}")]
+pub struct RegistryLoadError {
+    pub path: PathBuf,
+    #[source]
+    pub source: TextureLoadError,
+}
+
+/// Holds every texture used by the game. Textures are referred to by their id
+/// (like in maps and `Raycaster`) or by their name (see `tex_name`).
+///
+/// Ids are given out in the order in which textures are added. A file added
+/// more than once (even through different paths) is loaded only once and all
+/// its ids share the same texture.
+///
+/// Textures are behind an `Arc` so that they can be handed out to other
+/// threads. The registry itself is usually shared in an `Arc` too, say
+/// between multiple renderers.
+#[derive(Default)]
+pub struct TextureRegistry {
+    textures: Vec<Arc<Texture>>,
+    names: Vec<String>,
+    by_name: HashMap<String, usize>,
+    /// Keyed by canonical paths. See `canonical_path`.
+    by_path: HashMap<PathBuf, usize>,
+}
+
+impl TextureRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a registry with the textures at `paths`. The texture at
+    /// `paths[i]` gets the id `i`.
+    pub fn load<P: AsRef<Path>>(paths: &[P]) -> Result<Self, RegistryLoadError> {
+        let mut registry = Self::new();
+
+        for path in paths {
+            registry.add(path)?;
+        }
+
+        Ok(registry)
+    }
+
+    /// Loads the texture at `path` and returns its id.
+    ///
+    /// If another texture already has the same name, lookups by that name keep
+    /// returning the older texture.
+    pub fn add<P: AsRef<Path>>(&mut self, path: P) -> Result<usize, RegistryLoadError> {
+        let path = path.as_ref();
+        let id = self.textures.len();
+        let key = canonical_path(path);
+
+        let texture = match self.by_path.get(&key) {
+            Some(&other) => {
+                debug!("{:?} is already loaded as texture {}", path, other);
+                Arc::clone(&self.textures[other])
+            }
+            None => {
+                let texture = Texture::load(path).and_then(check_size).map_err(|source| {
+                    RegistryLoadError {
+                        path: path.to_owned(),
+                        source,
+                    }
+                })?;
+                self.by_path.insert(key, id);
+                Arc::new(texture)
+            }
+        };
+
+        let name = tex_name(path);
+        self.by_name.entry(name.clone()).or_insert(id);
+        self.names.push(name);
+        self.textures.push(texture);
+
+        Ok(id)
+    }
+
+    /// Returns the texture with id `id`.
+    pub fn get(&self, id: usize) -> Option<&Arc<Texture>> {
+        self.textures.get(id)
+    }
+
+    /// Returns the id of the texture named `name`.
+    pub fn id(&self, name: &str) -> Option<usize> {
+        self.by_name.get(name).copied()
+    }
+
+    /// Returns the texture named `name`.
+    pub fn by_name(&self, name: &str) -> Option<&Arc<Texture>> {
+        self.id(name).and_then(|id| self.get(id))
+    }
+
+    /// Returns the names of all the textures ordered by id. Maps use these to
+    /// refer to textures.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+}
+
+impl Index<usize> for TextureRegistry {
+    type Output = Texture;
+
+    fn index(&self, id: usize) -> &Texture {
+        &self.textures[id]
+    }
+}
+
+impl fmt::Debug for TextureRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TextureRegistry")
+            .field("names", &self.names)
+            .finish()
+    }
+}
+
+/// Returns the canonical form of `path` so that different paths to the same
+/// file are recognized. Falls back to `path` itself if it can't be resolved.
+fn canonical_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+fn check_size(texture: Texture) -> Result<Texture, TextureLoadError> {
+    let (wd, ht) = (texture.wd, texture.ht);
+
+    if wd == 0 || ht == 0 || wd > MAX_TEX_SIZE || ht > MAX_TEX_SIZE {
+        Err(TextureLoadError::BadSize { wd, ht })
+    } else {
+        Ok(texture)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_gives_ids_in_order_and_shares_duplicates() {
+        let paths = ["res/tex/eagle.png", "res/tex/wood.png", "res/tex/eagle.png"];
+        let registry = TextureRegistry::load(&paths).unwrap();
+
+        assert_eq!(registry.len(), 3);
+        assert_eq!(registry.names(), ["eagle", "wood", "eagle"]);
+        assert!(Arc::ptr_eq(
+            registry.get(0).unwrap(),
+            registry.get(2).unwrap()
+        ));
+        assert!(!Arc::ptr_eq(
+            registry.get(0).unwrap(),
+            registry.get(1).unwrap()
+        ));
+    }
+
+    #[test]
+    fn load_shares_different_paths_to_same_file() {
+        let paths = [
+            "res/tex/eagle.png",
+            "./res/tex/eagle.png",
+            "res/../res/tex/eagle.png",
+        ];
+        let registry = TextureRegistry::load(&paths).unwrap();
+
+        assert_eq!(registry.len(), 3);
+        assert!(Arc::ptr_eq(
+            registry.get(0).unwrap(),
+            registry.get(1).unwrap()
+        ));
+        assert!(Arc::ptr_eq(
+            registry.get(0).unwrap(),
+            registry.get(2).unwrap()
+        ));
+    }
+
+    #[test]
+    fn lookup_by_name() {
+        let registry = TextureRegistry::load(&["res/tex/eagle.png", "res/tex/wood.png"]).unwrap();
+
+        assert_eq!(registry.id("wood"), Some(1));
+        assert_eq!(registry.id("marble"), None);
+        assert!(Arc::ptr_eq(
+            registry.by_name("eagle").unwrap(),
+            registry.get(0).unwrap()
+        ));
+    }
+
+    #[test]
+    fn load_reports_path_of_bad_texture() {
+        let err = TextureRegistry::load(&["res/tex/eagle.png", "res/tex/missing.png"]).unwrap_err();
+
+        assert_eq!(err.path, Path::new("res/tex/missing.png"));
+        assert!(matches!(err.source, TextureLoadError::IoError(_)));
+    }
+}
@@ -0,0 +1,100 @@
+use std::time::{Duration, Instant};
+
+/// Max ticks run per frame. If the simulation falls behind by more than this,
+/// the extra time is dropped instead of trying to catch up.
+const MAX_TICKS_PER_FRAME: u32 = 8;
+
+/// Keeps track of how many fixed-length ticks of simulation are due.
+///
+/// # Overall idea
+///
+/// The time elapsed since the last call to `advance` is added to an
+/// accumulator. Whole ticks are taken out of it and run. The leftover (less
+/// than a tick) carries over to the next frame.
+///
+/// The leftover tells how far the present is between the last tick and the
+/// next one. Rendering interpolates between the last 2 ticks using it (see
+/// `alpha`). This keeps motion smooth when the frame rate isn't a multiple of
+/// the tick rate.
+#[derive(Debug)]
+pub struct FixedTimestep {
+    tick: Duration,
+    acc: Duration,
+    last: Instant,
+}
+
+impl FixedTimestep {
+    /// Creates a timestep running `tick_rate` ticks per second, starting at
+    /// `now`.
+    pub fn new(tick_rate: f32, now: Instant) -> Self {
+        FixedTimestep {
+            tick: Duration::from_secs_f32(1.0 / tick_rate),
+            acc: Duration::ZERO,
+            last: now,
+        }
+    }
+
+    /// Length of a tick in seconds.
+    pub fn dt(&self) -> f32 {
+        self.tick.as_secs_f32()
+    }
+
+    /// Accounts for the time elapsed till `now` and returns the number of
+    /// ticks which should be run.
+    pub fn advance(&mut self, now: Instant) -> u32 {
+        self.acc += now.saturating_duration_since(self.last);
+        self.last = now;
+
+        let mut ticks = 0;
+
+        while self.acc >= self.tick {
+            self.acc -= self.tick;
+            ticks += 1;
+        }
+
+        if ticks > MAX_TICKS_PER_FRAME {
+            warn!(
+                "Simulation is {} ticks behind. Skipping them",
+                ticks - MAX_TICKS_PER_FRAME
+            );
+            ticks = MAX_TICKS_PER_FRAME;
+        }
+
+        ticks
+    }
+
+    /// Returns how far the present is between the last tick and the next one.
+    /// 0 means that the last tick just happened. It is less than 1.
+    pub fn alpha(&self) -> f32 {
+        self.acc.as_secs_f32() / self.tick.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advance_runs_whole_ticks_and_carries_leftover() {
+        let start = Instant::now();
+        let mut timestep = FixedTimestep::new(10.0, start);
+
+        assert_eq!(timestep.advance(start + Duration::from_millis(250)), 2);
+        assert!((timestep.alpha() - 0.5).abs() < 1e-3);
+
+        assert_eq!(timestep.advance(start + Duration::from_millis(320)), 1);
+        assert!((timestep.alpha() - 0.2).abs() < 1e-3);
+    }
+
+    #[test]
+    fn advance_caps_ticks_after_a_stall() {
+        let start = Instant::now();
+        let mut timestep = FixedTimestep::new(10.0, start);
+
+        assert_eq!(
+            timestep.advance(start + Duration::from_secs(60)),
+            MAX_TICKS_PER_FRAME
+        );
+        assert_eq!(timestep.advance(start + Duration::from_secs(60)), 0);
+    }
+}
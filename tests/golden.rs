@@ -0,0 +1,533 @@
+//! Golden image tests for the renderer.
+//!
+//! Each case renders a fixture map from a fixed camera pose and compares the
+//! result with a reference image in `tests/golden`. If they differ by more than
+//! the tolerance, a diff image is written to `target/golden-diff` and the test
+//! fails. Differing pixels are red in the diff image. Matching pixels are dimmed
+//! copies of the reference.
+//!
+//! Run with `UPDATE_GOLDEN=1` to (re)generate the reference images after an
+//! intentional change to the renderer's output.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use raycaster::{
+    camera::Camera,
+    fog::Fog,
+    frame::Frame,
+    map::{Map, TexId},
+    primitive::Vec2,
+    raycaster::{Raycaster, Scratch},
+    sprite::Sprite,
+    texture::{tex_name, Sampling, Texture, TextureRegistry},
+};
+
+const SCR_WD: u32 = 160;
+const SCR_HT: u32 = 120;
+
+/// Max difference allowed in a single channel of a pixel.
+const CHANNEL_TOLERANCE: u8 = 2;
+/// Max fraction of pixels allowed to exceed `CHANNEL_TOLERANCE`.
+const PIXEL_TOLERANCE: f32 = 0.001;
+
+const TEXTURES: [&str; 10] = [
+    "res/tex/eagle.png",
+    "res/tex/red_brick.png",
+    "res/tex/purple_stone.png",
+    "res/tex/grey_stone.png",
+    "res/tex/blue_stone.png",
+    "res/tex/moss_stone.png",
+    "res/tex/wood.png",
+    "res/tex/color_stone.png",
+    "res/tex/barrel.png",
+    "res/tex/sky.png",
+];
+
+const BARREL: TexId = 8;
+const SKY: usize = 9;
+
+struct Case<'a> {
+    name: &'a str,
+    map: &'a str,
+    pos: Vec2,
+    dir: Vec2,
+    sprites: &'a [Sprite],
+}
+
+fn root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn make_caster(threads: usize) -> Raycaster {
+    Raycaster {
+        scr_wd: SCR_WD,
+        scr_ht: SCR_HT,
+        textures: Arc::new(TextureRegistry::load(&TEXTURES.map(|path| root().join(path))).unwrap()),
+        floor_id: 3,
+        wall_ht_scale: 1.0,
+        max_dist: 100.0,
+        threads,
+        fog: None,
+        sky_id: None,
+        mipmaps: false,
+        sampling: Sampling::Nearest,
+        scratch: Scratch::default(),
+    }
+}
+
+fn render(case: &Case, caster: &Raycaster, setup: impl FnOnce(&mut Map, &mut Camera)) -> Frame {
+    let tex_names: Vec<_> = TEXTURES.iter().map(tex_name).collect();
+    let mut map = Map::load(root().join("tests/fixtures").join(case.map), &tex_names).unwrap();
+    let mut camera = Camera::new(case.pos, case.dir.normalized(), 90.0);
+    camera.set_aspect(caster.scr_wd as f32 / caster.scr_ht as f32);
+    setup(&mut map, &mut camera);
+
+    let mut frame = Frame::new(caster.scr_wd, caster.scr_ht);
+    caster.render(&camera, &map, case.sprites, &mut frame.buf);
+    frame
+}
+
+/// Returns a diff image if `actual` doesn't match `expected`.
+fn compare(actual: &Frame, expected: &Texture) -> Option<Frame> {
+    assert_eq!(
+        (actual.wd as usize, actual.ht as usize),
+        (expected.wd, expected.ht),
+        "Reference image has different dimensions"
+    );
+
+    let mut diff = Frame::new(actual.wd, actual.ht);
+    let mut mismatches = 0;
+
+    for i in (0..actual.buf.len()).step_by(4) {
+        let exceeds = (0..4).any(|c| {
+            let a = actual.buf[i + c];
+            let e = expected.buf[i + c];
+            a.max(e) - a.min(e) > CHANNEL_TOLERANCE
+        });
+
+        if exceeds {
+            mismatches += 1;
+            diff.buf[i..i + 4].copy_from_slice(&[255, 0, 0, 255]);
+        } else {
+            for c in 0..3 {
+                diff.buf[i + c] = expected.buf[i + c] / 4;
+            }
+            diff.buf[i + 3] = 255;
+        }
+    }
+
+    let pixels = (actual.wd * actual.ht) as f32;
+
+    if mismatches as f32 / pixels > PIXEL_TOLERANCE {
+        Some(diff)
+    } else {
+        None
+    }
+}
+
+fn check(case: Case) {
+    check_with(case, |_, _| {});
+}
+
+/// Like `check` but lets `setup` modify the map and camera before rendering.
+fn check_with(case: Case, setup: impl FnOnce(&mut Map, &mut Camera)) {
+    // An uneven split so that the boundaries between strips get exercised.
+    check_using(case, &make_caster(7), setup);
+}
+
+/// Like `check_with` but renders using `caster`.
+fn check_using(case: Case, caster: &Raycaster, setup: impl FnOnce(&mut Map, &mut Camera)) {
+    let actual = render(&case, caster, setup);
+    let golden = root()
+        .join("tests/golden")
+        .join(format!("{}.png", case.name));
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        actual.save(&golden).unwrap();
+        return;
+    }
+
+    assert!(
+        Path::exists(&golden),
+        "Missing reference image {:?}. Run with UPDATE_GOLDEN=1 to create it.",
+        golden
+    );
+
+    let expected = Texture::load(&golden).unwrap();
+
+    if let Some(diff) = compare(&actual, &expected) {
+        let diff_dir = root().join("target/golden-diff");
+        fs::create_dir_all(&diff_dir).unwrap();
+
+        let actual_path = diff_dir.join(format!("{}.actual.png", case.name));
+        let diff_path = diff_dir.join(format!("{}.diff.png", case.name));
+        actual.save(&actual_path).unwrap();
+        diff.save(&diff_path).unwrap();
+
+        panic!(
+            "{} doesn't match its reference image. See {:?} and {:?}.",
+            case.name, actual_path, diff_path
+        );
+    }
+}
+
+#[test]
+fn room_facing_east() {
+    check(Case {
+        name: "room_facing_east",
+        map: "room.map",
+        pos: Vec2::new(2.5, 3.0),
+        dir: Vec2::new(1.0, 0.0),
+        sprites: &[],
+    });
+}
+
+/// The v2 version of the room should look exactly like the v1 version.
+#[test]
+fn room_v2_facing_east() {
+    check(Case {
+        name: "room_facing_east",
+        map: "room.toml",
+        pos: Vec2::new(2.5, 3.0),
+        dir: Vec2::new(1.0, 0.0),
+        sprites: &[],
+    });
+}
+
+#[test]
+fn room_facing_corner() {
+    check(Case {
+        name: "room_facing_corner",
+        map: "room.map",
+        pos: Vec2::new(3.2, 2.7),
+        dir: Vec2::new(1.0, 1.0),
+        sprites: &[],
+    });
+}
+
+#[test]
+fn room_close_to_wall() {
+    check(Case {
+        name: "room_close_to_wall",
+        map: "room.map",
+        pos: Vec2::new(6.7, 2.3),
+        dir: Vec2::new(1.0, -0.2),
+        sprites: &[],
+    });
+}
+
+#[test]
+fn pillars_with_sprites() {
+    check(Case {
+        name: "pillars_with_sprites",
+        map: "pillars.map",
+        pos: Vec2::new(1.5, 3.5),
+        dir: Vec2::new(1.0, 0.1),
+        sprites: &[
+            Sprite::new(Vec2::new(4.5, 3.5), BARREL),
+            Sprite::new(Vec2::new(7.5, 2.5), BARREL),
+            Sprite::new(Vec2::new(7.5, 5.5), BARREL),
+        ],
+    });
+}
+
+/// Splitting the work across threads shouldn't change even a single pixel.
+#[test]
+fn thread_count_doesnt_change_output() {
+    let case = Case {
+        name: "pillars_with_one_sprite",
+        map: "pillars.map",
+        pos: Vec2::new(1.5, 3.5),
+        dir: Vec2::new(1.0, 0.1),
+        sprites: &[Sprite::new(Vec2::new(4.5, 3.5), BARREL)],
+    };
+    let expected = render(&case, &make_caster(1), |_, _| {});
+
+    for threads in [2, 7, SCR_HT as usize + 1] {
+        let actual = render(&case, &make_caster(threads), |_, _| {});
+        assert!(
+            actual.buf == expected.buf,
+            "Output differs with {} threads",
+            threads
+        );
+    }
+}
+
+/// Buffers reused from an earlier frame shouldn't leak into the next one.
+#[test]
+fn reusing_caster_doesnt_change_output() {
+    let case = Case {
+        name: "pillars_with_one_sprite",
+        map: "pillars.map",
+        pos: Vec2::new(1.5, 3.5),
+        dir: Vec2::new(1.0, 0.1),
+        sprites: &[Sprite::new(Vec2::new(4.5, 3.5), BARREL)],
+    };
+    let expected = render(&case, &make_caster(7), |_, _| {});
+
+    let mut caster = make_caster(7);
+    caster.scr_wd = 2 * SCR_WD;
+    render(&case, &caster, |_, camera| camera.eye_ht = 0.9);
+    caster.scr_wd = SCR_WD;
+    let actual = render(&case, &caster, |_, _| {});
+
+    assert!(
+        actual.buf == expected.buf,
+        "Output differs after reusing the caster"
+    );
+}
+
+#[test]
+fn pillars_facing_west() {
+    check(Case {
+        name: "pillars_facing_west",
+        map: "pillars.map",
+        pos: Vec2::new(8.5, 4.5),
+        dir: Vec2::new(-1.0, 0.0),
+        sprites: &[Sprite::new(Vec2::new(3.5, 3.5), BARREL)],
+    });
+}
+
+#[test]
+fn open_map_looking_out() {
+    check(Case {
+        name: "open_map_looking_out",
+        map: "open.map",
+        pos: Vec2::new(2.5, 2.5),
+        dir: Vec2::new(1.0, 0.0),
+        sprites: &[],
+    });
+}
+
+#[test]
+fn rooms_with_floor_and_ceiling_layers() {
+    check(Case {
+        name: "rooms_with_floor_and_ceiling_layers",
+        map: "rooms.toml",
+        pos: Vec2::new(1.5, 3.5),
+        dir: Vec2::new(1.0, 0.0),
+        sprites: &[],
+    });
+}
+
+#[test]
+fn doors_closed() {
+    check(Case {
+        name: "doors_closed",
+        map: "doors.toml",
+        pos: Vec2::new(1.5, 2.5),
+        dir: Vec2::new(1.0, 0.15),
+        sprites: &[],
+    });
+}
+
+#[test]
+fn doors_half_open() {
+    let case = Case {
+        name: "doors_half_open",
+        map: "doors.toml",
+        pos: Vec2::new(1.5, 2.5),
+        dir: Vec2::new(1.0, 0.15),
+        sprites: &[],
+    };
+
+    check_with(case, |map, _| {
+        for door in map.doors.iter_mut().flatten() {
+            door.openness = 0.5;
+        }
+    });
+}
+
+#[test]
+fn pillars_looking_up_while_crouching() {
+    let case = Case {
+        name: "pillars_looking_up_while_crouching",
+        map: "pillars.map",
+        pos: Vec2::new(1.5, 3.5),
+        dir: Vec2::new(1.0, 0.1),
+        sprites: &[Sprite::new(Vec2::new(4.5, 3.5), BARREL)],
+    };
+
+    check_with(case, |_, camera| {
+        camera.pitch = 0.2;
+        camera.eye_ht = 0.3;
+    });
+}
+
+#[test]
+fn pillars_looking_down() {
+    let case = Case {
+        name: "pillars_looking_down",
+        map: "pillars.map",
+        pos: Vec2::new(1.5, 3.5),
+        dir: Vec2::new(1.0, 0.1),
+        sprites: &[Sprite::new(Vec2::new(4.5, 3.5), BARREL)],
+    };
+
+    check_with(case, |_, camera| camera.pitch = -0.25);
+}
+
+#[test]
+fn pillars_in_fog() {
+    let case = Case {
+        name: "pillars_in_fog",
+        map: "pillars.map",
+        pos: Vec2::new(1.5, 3.5),
+        dir: Vec2::new(1.0, 0.1),
+        sprites: &[Sprite::new(Vec2::new(4.5, 3.5), BARREL)],
+    };
+    let mut caster = make_caster(7);
+    caster.fog = Some(Fog {
+        color: [40, 50, 70],
+        start: 1.0,
+        end: 8.0,
+    });
+
+    check_using(case, &caster, |_, _| {});
+}
+
+#[test]
+fn rooms_lit_by_point_lights() {
+    check(Case {
+        name: "rooms_lit_by_point_lights",
+        map: "lights.toml",
+        pos: Vec2::new(1.5, 3.5),
+        dir: Vec2::new(1.0, -0.2),
+        sprites: &[Sprite::new(Vec2::new(3.5, 2.5), BARREL)],
+    });
+}
+
+#[test]
+fn courtyard_under_sky() {
+    let case = Case {
+        name: "courtyard_under_sky",
+        map: "courtyard.toml",
+        pos: Vec2::new(1.5, 3.5),
+        dir: Vec2::new(1.0, 0.3),
+        sprites: &[],
+    };
+    let mut caster = make_caster(7);
+    caster.sky_id = Some(SKY);
+
+    check_using(case, &caster, |_, camera| camera.pitch = 0.1);
+}
+
+/// Rays leaving an open map show the sky.
+#[test]
+fn open_map_under_sky() {
+    let case = Case {
+        name: "open_map_under_sky",
+        map: "open.map",
+        pos: Vec2::new(2.5, 2.5),
+        dir: Vec2::new(1.0, 0.0),
+        sprites: &[],
+    };
+    let mut caster = make_caster(7);
+    caster.sky_id = Some(SKY);
+
+    check_using(case, &caster, |_, _| {});
+}
+
+/// Distant walls and the floor near the horizon are sampled from smaller mip
+/// levels.
+#[test]
+fn open_map_mipmapped() {
+    let case = Case {
+        name: "open_map_mipmapped",
+        map: "open.map",
+        pos: Vec2::new(1.5, 1.5),
+        dir: Vec2::new(1.0, 0.4),
+        sprites: &[],
+    };
+    let mut caster = make_caster(7);
+    caster.mipmaps = true;
+
+    check_using(case, &caster, |_, _| {});
+}
+
+/// Up close, bilinear sampling blends neighbouring texels of walls and floors.
+#[test]
+fn pillars_bilinear() {
+    let case = Case {
+        name: "pillars_bilinear",
+        map: "pillars.map",
+        pos: Vec2::new(3.4, 4.4),
+        dir: Vec2::new(1.0, 0.3),
+        sprites: &[],
+    };
+    let mut caster = make_caster(7);
+    caster.sampling = Sampling::Bilinear;
+
+    check_using(case, &caster, |_, camera| camera.eye_ht = 0.3);
+}
+
+/// A 16:9 screen sees more on the sides than a 4:3 one, but things keep their
+/// proportions.
+#[test]
+fn pillars_widescreen() {
+    let case = Case {
+        name: "pillars_widescreen",
+        map: "pillars.map",
+        pos: Vec2::new(1.5, 3.5),
+        dir: Vec2::new(1.0, 0.1),
+        sprites: &[Sprite::new(Vec2::new(4.5, 3.5), BARREL)],
+    };
+    let mut caster = make_caster(7);
+    caster.scr_wd = 192;
+    caster.scr_ht = 108;
+
+    check_using(case, &caster, |_, _| {});
+}
+
+/// Walls of different heights seen from above the low ones. Low walls show
+/// their tops and what is behind them. A tall tower rises above the rest.
+#[test]
+fn walls_of_different_heights() {
+    let case = Case {
+        name: "walls_of_different_heights",
+        map: "heights.toml",
+        pos: Vec2::new(10.5, 5.5),
+        dir: Vec2::new(-1.0, -0.6),
+        sprites: &[Sprite::new(Vec2::new(5.5, 3.5), BARREL)],
+    };
+    let mut caster = make_caster(7);
+    caster.sky_id = Some(SKY);
+
+    check_using(case, &caster, |_, camera| {
+        camera.pitch = -0.1;
+        camera.eye_ht = 0.8;
+    });
+}
+
+/// Looking into a room whose walls are brick outside and wood inside.
+#[test]
+fn room_with_face_textures() {
+    check(Case {
+        name: "room_with_face_textures",
+        map: "faces.toml",
+        pos: Vec2::new(8.5, 5.5),
+        dir: Vec2::new(-1.0, -0.4),
+        sprites: &[],
+    });
+}
+
+/// Low walls sampled bilinearly from mip levels, including ones seen at a
+/// grazing angle along the bottom row.
+#[test]
+fn walls_of_different_heights_bilinear_mipmapped() {
+    let case = Case {
+        name: "walls_of_different_heights_bilinear_mipmapped",
+        map: "heights.toml",
+        pos: Vec2::new(1.2, 5.25),
+        dir: Vec2::new(1.0, 0.0),
+        sprites: &[],
+    };
+    let mut caster = make_caster(7);
+    caster.mipmaps = true;
+    caster.sampling = Sampling::Bilinear;
+
+    check_using(case, &caster, |_, _| {});
+}